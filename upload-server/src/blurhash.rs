@@ -0,0 +1,124 @@
+//! BlurHash placeholder encoding.
+//!
+//! Encodes a tiny, DCT-style approximation of an image into a short Base83
+//! string, so the gallery can paint a blurred placeholder the instant a new
+//! image's metadata is known, instead of an empty box while the real
+//! thumbnail is still being generated client-side.
+
+use image::RgbImage;
+
+const BASE83_ALPHABET: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn encode_base83(mut value: u32, length: usize) -> String {
+    let mut chars = vec![0u8; length];
+    for i in (0..length).rev() {
+        let digit = value % 83;
+        chars[i] = BASE83_ALPHABET[digit as usize];
+        value /= 83;
+    }
+    String::from_utf8(chars).expect("Base83 alphabet is ASCII")
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+    let encoded = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Signed power: preserves the sign of `value` while raising its magnitude
+/// to `exp`, since blurhash's AC components can be negative.
+fn sign_pow(value: f32, exp: f32) -> f32 {
+    value.signum() * value.abs().powf(exp)
+}
+
+/// One DCT-style basis function's average color over the whole image,
+/// weighted by `cos(pi*i*x/width) * cos(pi*j*y/height)`.
+fn basis_factor(img: &RgbImage, i: u32, j: u32) -> [f32; 3] {
+    let (width, height) = img.dimensions();
+    let mut sum = [0.0f32; 3];
+
+    for y in 0..height {
+        let cos_y = (std::f32::consts::PI * j as f32 * y as f32 / height as f32).cos();
+        for x in 0..width {
+            let cos_x = (std::f32::consts::PI * i as f32 * x as f32 / width as f32).cos();
+            let basis = cos_x * cos_y;
+            let pixel = img.get_pixel(x, y);
+            sum[0] += basis * srgb_to_linear(pixel.0[0]);
+            sum[1] += basis * srgb_to_linear(pixel.0[1]);
+            sum[2] += basis * srgb_to_linear(pixel.0[2]);
+        }
+    }
+
+    let normalization = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+    let scale = normalization / (width as f32 * height as f32);
+    [sum[0] * scale, sum[1] * scale, sum[2] * scale]
+}
+
+fn encode_dc(color: [f32; 3]) -> u32 {
+    let r = linear_to_srgb(color[0]) as u32;
+    let g = linear_to_srgb(color[1]) as u32;
+    let b = linear_to_srgb(color[2]) as u32;
+    (r << 16) + (g << 8) + b
+}
+
+fn encode_ac(color: [f32; 3], max_value: f32) -> u32 {
+    let quantize = |c: f32| -> u32 {
+        (sign_pow(c / max_value, 0.5) * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32
+    };
+    quantize(color[0]) * 19 * 19 + quantize(color[1]) * 19 + quantize(color[2])
+}
+
+/// Encodes `img` into a BlurHash string using `x_components x y_components`
+/// basis functions (each in `1..=9`). A typical choice is 4x3, producing a
+/// ~28-character string.
+pub fn encode(img: &RgbImage, x_components: u32, y_components: u32) -> String {
+    let mut factors = Vec::with_capacity((x_components * y_components) as usize);
+    for j in 0..y_components {
+        for i in 0..x_components {
+            factors.push(basis_factor(img, i, j));
+        }
+    }
+
+    let dc = factors[0];
+    let ac = &factors[1..];
+
+    let size_flag = (x_components - 1) + (y_components - 1) * 9;
+    let mut result = encode_base83(size_flag, 1);
+
+    if ac.is_empty() {
+        result.push_str(&encode_base83(0, 1));
+        result.push_str(&encode_base83(encode_dc(dc), 4));
+        return result;
+    }
+
+    let max_ac = ac
+        .iter()
+        .flat_map(|c| c.iter())
+        .copied()
+        .fold(0.0f32, |acc, v| acc.max(v.abs()));
+
+    let quantised_max_ac = ((max_ac * 166.0 - 0.5).floor() as i32).clamp(0, 82) as u32;
+    let actual_max_value = (quantised_max_ac + 1) as f32 / 166.0;
+
+    result.push_str(&encode_base83(quantised_max_ac, 1));
+    result.push_str(&encode_base83(encode_dc(dc), 4));
+    for color in ac {
+        result.push_str(&encode_base83(encode_ac(*color, actual_max_value), 2));
+    }
+
+    result
+}
@@ -1,11 +1,22 @@
+use exif::{In, Tag};
 use glob::glob;
 use image::imageops::FilterType;
+use image::DynamicImage;
+use lru::LruCache;
 use rocket::fairing::{Fairing, Info, Kind, Result};
 use rocket::{Build, Rocket, Orbit};
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use tokio::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Notify, Semaphore};
+use tokio::time;
 
+use crate::derived_manifest;
 use crate::metadata;
+use crate::store::ImageStore;
 
 // Inky Impression display resolution.
 pub const DISPLAY_WIDTH: u32 = 600;
@@ -19,6 +30,175 @@ pub enum CacheRequest {
     CreateCache(PathBuf, String),
 }
 
+/// Default number of encoded previews to keep in the in-memory LRU cache.
+/// Can be overridden via PREVIEW_CACHE_CAPACITY for Pi-constrained deployments.
+const DEFAULT_PREVIEW_CACHE_CAPACITY: usize = 32;
+
+fn get_preview_cache_capacity() -> usize {
+    std::env::var("PREVIEW_CACHE_CAPACITY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(DEFAULT_PREVIEW_CACHE_CAPACITY)
+}
+
+/// Returns a stable discriminant for a `FilterType`, since it doesn't implement `Hash`.
+fn filter_discriminant(filter: FilterType) -> u8 {
+    match filter {
+        FilterType::Nearest => 0,
+        FilterType::Triangle => 1,
+        FilterType::CatmullRom => 2,
+        FilterType::Gaussian => 3,
+        FilterType::Lanczos3 => 4,
+    }
+}
+
+/// Key for a cached preview: the original image path plus the filter used to resize it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PreviewCacheKey {
+    original_path: PathBuf,
+    filter: u8,
+}
+
+impl PreviewCacheKey {
+    fn new(original_path: &Path, filter: FilterType) -> Self {
+        Self {
+            original_path: original_path.to_path_buf(),
+            filter: filter_discriminant(filter),
+        }
+    }
+}
+
+/// A cached, already-encoded preview.
+#[derive(Debug, Clone)]
+struct CachedPreview {
+    bytes: Vec<u8>,
+    created_at: Instant,
+}
+
+/// Shared, bounded in-memory cache of encoded preview PNGs.
+pub type PreviewCacheState = Arc<Mutex<LruCache<PreviewCacheKey, CachedPreview>>>;
+
+/// Builds a fresh preview cache sized from `PREVIEW_CACHE_CAPACITY` (or the default).
+pub fn new_preview_cache() -> PreviewCacheState {
+    let capacity = get_preview_cache_capacity();
+    let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::new(DEFAULT_PREVIEW_CACHE_CAPACITY).unwrap());
+    Arc::new(Mutex::new(LruCache::new(capacity)))
+}
+
+/// Tracks the in-progress state of a cache file write so readers can wait for it
+/// instead of racing the filesystem and observing a truncated PNG.
+struct CacheWriteStatus {
+    notify: Notify,
+    done: AtomicBool,
+}
+
+/// Registry of cache keys currently being written, keyed the same way the
+/// cache `ImageStore` is (a bare filename like `photo.jpg.png`), so readers
+/// going through the store can look a key up without knowing it's backed by
+/// a local path.
+static WRITE_STATUS: LazyLock<RwLock<HashMap<String, Arc<CacheWriteStatus>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Registers `cache_key` as being written. Must be paired with `finish_write`.
+fn begin_write(cache_key: &str) -> Arc<CacheWriteStatus> {
+    let status = Arc::new(CacheWriteStatus {
+        notify: Notify::new(),
+        done: AtomicBool::new(false),
+    });
+
+    WRITE_STATUS
+        .write()
+        .unwrap()
+        .insert(cache_key.to_string(), status.clone());
+
+    status
+}
+
+/// Marks a cache key's write as finished and wakes any waiting readers.
+fn finish_write(cache_key: &str, status: &Arc<CacheWriteStatus>) {
+    status.done.store(true, Ordering::SeqCst);
+    status.notify.notify_waiters();
+    WRITE_STATUS.write().unwrap().remove(cache_key);
+}
+
+/// Reads a cache-tree key through `store`, waiting first for any write to
+/// the same key already in progress (`create_cached_image`, running on the
+/// background cache worker) to finish, rather than racing it and observing
+/// a truncated file. Called from `image_serving::respond` for keys under the
+/// `cache/` prefix - `dithered`/`thumbs` are written synchronously within a
+/// single request and don't have this in-flight-write window. Access
+/// bookkeeping (`record_cache_access`) stays the caller's responsibility, so
+/// it still runs for conditional-GET `304` responses that never reach here.
+pub async fn read_cache_file(key: &str, store: &Arc<dyn ImageStore>) -> std::result::Result<Vec<u8>, String> {
+    let in_progress = WRITE_STATUS.read().unwrap().get(key).cloned();
+    if let Some(status) = in_progress {
+        if !status.done.load(Ordering::SeqCst) {
+            status.notify.notified().await;
+        }
+    }
+
+    store.get(key).await
+}
+
+/// Reads the EXIF `Orientation` tag (1-8) from an image file.
+/// Returns 1 (identity) when no EXIF data is present or it can't be parsed.
+fn read_exif_orientation(path: &Path) -> u8 {
+    let Ok(file) = std::fs::File::open(path) else { return 1 };
+    let mut reader = std::io::BufReader::new(file);
+
+    let Ok(exif) = exif::Reader::new().read_from_container(&mut reader) else { return 1 };
+
+    exif.get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .map(|v| v as u8)
+        .filter(|v| (1..=8).contains(v))
+        .unwrap_or(1)
+}
+
+/// Applies one of the 8 standard EXIF orientation transforms to a decoded image.
+fn apply_exif_orientation(img: DynamicImage, orientation: u8) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Opens an image and normalizes its orientation according to its EXIF tag (if any).
+pub fn open_oriented(path: &Path) -> std::result::Result<DynamicImage, String> {
+    let img = image::open(path).map_err(|e| format!("Failed to open image: {}", e))?;
+    let orientation = read_exif_orientation(path);
+    Ok(apply_exif_orientation(img, orientation))
+}
+
+/// Reads the EXIF `Orientation` tag (1-8) from in-memory image bytes.
+/// Returns 1 (identity) when no EXIF data is present or it can't be parsed.
+fn read_exif_orientation_bytes(bytes: &[u8]) -> u8 {
+    let mut cursor = std::io::Cursor::new(bytes);
+    let Ok(exif) = exif::Reader::new().read_from_container(&mut cursor) else { return 1 };
+
+    exif.get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .map(|v| v as u8)
+        .filter(|v| (1..=8).contains(v))
+        .unwrap_or(1)
+}
+
+/// Like `open_oriented`, but decodes from in-memory bytes rather than a
+/// filesystem path, for callers reading through an `ImageStore` backend
+/// that isn't necessarily local disk (e.g. S3).
+pub fn open_oriented_bytes(bytes: &[u8]) -> std::result::Result<DynamicImage, String> {
+    let img = image::load_from_memory(bytes).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let orientation = read_exif_orientation_bytes(bytes);
+    Ok(apply_exif_orientation(img, orientation))
+}
+
 /// Creates a cached 600x448 version of an image for the e-ink display.
 /// Returns Ok(()) on success, or an error message on failure.
 pub fn create_cached_image(original_path: &Path, filter: FilterType) -> std::result::Result<(), String> {
@@ -33,22 +213,45 @@ pub fn create_cached_image(original_path: &Path, filter: FilterType) -> std::res
 
     println!("Creating cached image: {:?} -> {} (filter: {:?})", original_path, cache_path, filter);
 
-    let img = image::open(original_path)
-        .map_err(|e| format!("Failed to open image: {}", e))?;
+    // Mark this cache key as being written so concurrent readers wait for us
+    // instead of observing a half-written file.
+    let status = begin_write(&cache_filename);
 
-    let resized = img.resize_exact(DISPLAY_WIDTH, DISPLAY_HEIGHT, filter);
+    let result = (|| {
+        let img = open_oriented(original_path)?;
+
+        let resized = img.resize_exact(DISPLAY_WIDTH, DISPLAY_HEIGHT, filter);
+
+        resized.save(&cache_path)
+            .map_err(|e| format!("Failed to save cached image: {}", e))
+    })();
 
-    resized.save(&cache_path)
-        .map_err(|e| format!("Failed to save cached image: {}", e))?;
+    finish_write(&cache_filename, &status);
+
+    result?;
+
+    derived_manifest::register(filename, derived_manifest::DerivedTree::Cache, &cache_filename);
 
     println!("Cached image created: {}", cache_path);
     Ok(())
 }
 
 /// Resizes an image and returns the bytes (for preview, does not save to disk).
-pub fn resize_image_to_bytes(original_path: &Path, filter: FilterType) -> std::result::Result<Vec<u8>, String> {
-    let img = image::open(original_path)
-        .map_err(|e| format!("Failed to open image: {}", e))?;
+/// Consults `cache` first and only decodes/resizes/encodes on a miss.
+pub fn resize_image_to_bytes(
+    original_path: &Path,
+    filter: FilterType,
+    cache: &PreviewCacheState,
+) -> std::result::Result<Vec<u8>, String> {
+    let key = PreviewCacheKey::new(original_path, filter);
+
+    if let Ok(mut cache) = cache.lock() {
+        if let Some(cached) = cache.get(&key) {
+            return Ok(cached.bytes.clone());
+        }
+    }
+
+    let img = open_oriented(original_path)?;
 
     let resized = img.resize_exact(DISPLAY_WIDTH, DISPLAY_HEIGHT, filter);
 
@@ -57,9 +260,157 @@ pub fn resize_image_to_bytes(original_path: &Path, filter: FilterType) -> std::r
     resized.write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)
         .map_err(|e| format!("Failed to encode image: {}", e))?;
 
+    if let Ok(mut cache) = cache.lock() {
+        cache.put(
+            key,
+            CachedPreview {
+                bytes: bytes.clone(),
+                created_at: Instant::now(),
+            },
+        );
+    }
+
     Ok(bytes)
 }
 
+/// Default total budget, in bytes, for the cache directory.
+/// Can be overridden via CACHE_BUDGET_BYTES.
+const DEFAULT_CACHE_BUDGET_BYTES: u64 = 512 * 1024 * 1024;
+
+/// How often the janitor task re-checks the cache directory's total size.
+const CACHE_JANITOR_INTERVAL_SECS: u64 = 300;
+
+/// A cache file (or its original) served within this window is protected from eviction.
+const SERVE_GRACE: Duration = Duration::from_secs(30);
+
+fn get_cache_budget_bytes() -> u64 {
+    std::env::var("CACHE_BUDGET_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(DEFAULT_CACHE_BUDGET_BYTES)
+}
+
+/// Last-access times, keyed by cache filename, updated whenever a cache file is served.
+static CACHE_LAST_ACCESS: LazyLock<RwLock<HashMap<String, Instant>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Last-access times for original images, used as an eviction grace window: a cache
+/// file isn't evicted if its original was itself just served.
+static ORIGINAL_LAST_ACCESS: LazyLock<RwLock<HashMap<String, Instant>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Records that a cache file was just served, for LRU eviction bookkeeping.
+pub fn record_cache_access(filename: &str) {
+    CACHE_LAST_ACCESS
+        .write()
+        .unwrap()
+        .insert(filename.to_string(), Instant::now());
+}
+
+/// Records that an original image was just served, protecting its cache from
+/// eviction for a short grace window.
+pub fn record_original_access(filename: &str) {
+    ORIGINAL_LAST_ACCESS
+        .write()
+        .unwrap()
+        .insert(filename.to_string(), Instant::now());
+}
+
+fn served_within_grace(filename: &str) -> bool {
+    ORIGINAL_LAST_ACCESS
+        .read()
+        .unwrap()
+        .get(filename)
+        .is_some_and(|t| t.elapsed() < SERVE_GRACE)
+}
+
+/// Evicts least-recently-accessed cache files until the cache directory is back
+/// under the configured byte budget. Never evicts a cache whose original was
+/// itself just served.
+pub fn enforce_cache_budget() {
+    let budget = get_cache_budget_bytes();
+
+    let mut entries: Vec<(PathBuf, String, u64)> = Vec::new();
+    let mut total: u64 = 0;
+
+    for entry in glob("static/images/cache/*").expect("Failed to read glob pattern").flatten() {
+        if entry.is_dir() {
+            continue;
+        }
+        let Ok(meta) = std::fs::metadata(&entry) else { continue };
+        let size = meta.len();
+        total += size;
+
+        let filename = entry
+            .file_name()
+            .and_then(|f| f.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+        entries.push((entry, filename, size));
+    }
+
+    if total <= budget {
+        return;
+    }
+
+    // Evict oldest-accessed first. Entries never recorded as accessed (e.g. created
+    // at startup repair) are treated as the oldest, since they have no known recency.
+    let last_access = CACHE_LAST_ACCESS.read().unwrap();
+    entries.sort_by_key(|(_, filename, _)| {
+        std::cmp::Reverse(
+            last_access
+                .get(filename)
+                .map(|t| t.elapsed())
+                .unwrap_or(Duration::MAX),
+        )
+    });
+    drop(last_access);
+
+    let mut reclaimed = 0u64;
+    let mut removed = 0usize;
+
+    for (path, filename, size) in entries {
+        if total - reclaimed <= budget {
+            break;
+        }
+
+        // Strip the ".png" suffix cache files are saved with to recover the original name.
+        let original_name = filename.strip_suffix(".png").unwrap_or(&filename);
+        if served_within_grace(original_name) {
+            continue;
+        }
+
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                reclaimed += size;
+                removed += 1;
+                CACHE_LAST_ACCESS.write().unwrap().remove(&filename);
+            }
+            Err(e) => println!("Cache budget: failed to remove {:?}: {}", path, e),
+        }
+    }
+
+    if removed > 0 {
+        println!(
+            "Cache budget: evicted {} file(s), reclaimed {} bytes (budget {} bytes)",
+            removed, reclaimed, budget
+        );
+    }
+}
+
+/// Spawns a periodic janitor task that enforces the cache directory's byte budget.
+pub fn spawn_cache_budget_janitor() {
+    tokio::spawn(async {
+        let mut interval = time::interval(Duration::from_secs(CACHE_JANITOR_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+            tokio::task::spawn_blocking(enforce_cache_budget).await.ok();
+        }
+    });
+}
+
 /// Gets the cache path for a given original image path.
 /// Caches are always saved as PNG regardless of source format.
 pub fn get_cache_path(original_path: &str) -> String {
@@ -70,37 +421,87 @@ pub fn get_cache_path(original_path: &str) -> String {
     format!("static/images/cache/{}.png", filename)
 }
 
+/// Default number of cache-generation jobs allowed to run concurrently.
+/// Can be overridden via CACHE_WORKER_CONCURRENCY for beefier or more constrained hosts.
+const DEFAULT_CACHE_WORKER_CONCURRENCY: usize = 4;
+
+fn get_cache_worker_concurrency() -> usize {
+    std::env::var("CACHE_WORKER_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&v| v > 0)
+        .unwrap_or(DEFAULT_CACHE_WORKER_CONCURRENCY)
+}
+
 /// Spawns the background cache worker task.
 /// Must be called from within an async context (e.g., a fairing).
+///
+/// Dispatches each request through a bounded semaphore so a large startup
+/// repair pass doesn't overwhelm a Raspberry Pi's CPU/IO, while still allowing
+/// a handful of jobs to run in parallel instead of strictly serially.
 fn spawn_worker_task(mut rx: mpsc::Receiver<CacheRequest>) {
     tokio::spawn(async move {
         println!("Cache worker started, waiting for requests...");
 
+        let semaphore = Arc::new(Semaphore::new(get_cache_worker_concurrency()));
+        let mut in_flight = Vec::new();
+
         while let Some(request) = rx.recv().await {
             match request {
                 CacheRequest::CreateCache(path, filter_name) => {
                     println!("Cache worker received request for: {:?} (filter: {})", path, filter_name);
 
-                    // Run blocking image work in spawn_blocking to avoid blocking the async runtime.
-                    let path_clone = path.clone();
-                    let filter = metadata::parse_filter(&filter_name);
-                    let result = tokio::task::spawn_blocking(move || {
-                        create_cached_image(&path_clone, filter)
-                    }).await;
-
-                    match result {
-                        Ok(Ok(())) => println!("Background cache created: {:?}", path),
-                        Ok(Err(e)) => println!("Background cache failed for {:?}: {}", path, e),
-                        Err(e) => println!("Background task panicked for {:?}: {}", path, e),
-                    }
+                    let permit = semaphore.clone().acquire_owned().await
+                        .expect("cache worker semaphore should never be closed");
+
+                    let handle = tokio::task::spawn_blocking(move || {
+                        let filter = metadata::parse_filter(&filter_name);
+                        let result = create_cached_image(&path, filter);
+                        if result.is_ok() {
+                            // Opportunistically check the budget after each new cache lands.
+                            enforce_cache_budget();
+                        }
+                        drop(permit);
+                        (path, result)
+                    });
+
+                    in_flight.push(handle);
+                }
+            }
+
+            // Reap and log any jobs that have already finished, without blocking
+            // on ones still running. Completion order doesn't matter here.
+            let mut still_running = Vec::new();
+            for handle in in_flight.drain(..) {
+                if handle.is_finished() {
+                    log_job_result(handle.await);
+                } else {
+                    still_running.push(handle);
                 }
             }
+            in_flight = still_running;
+        }
+
+        // Channel closed: let any jobs still in flight finish before exiting.
+        for handle in in_flight {
+            log_job_result(handle.await);
         }
 
         println!("Cache worker shutting down.");
     });
 }
 
+/// Logs the outcome of a single background cache-creation job.
+fn log_job_result(
+    result: std::result::Result<(PathBuf, std::result::Result<(), String>), tokio::task::JoinError>,
+) {
+    match result {
+        Ok((path, Ok(()))) => println!("Background cache created: {:?}", path),
+        Ok((path, Err(e))) => println!("Background cache failed for {:?}: {}", path, e),
+        Err(e) => println!("Background task panicked: {}", e),
+    }
+}
+
 /// Fairing that sets up the cache worker and repairs missing caches on startup.
 pub struct CacheWorkerFairing;
 
@@ -120,7 +521,7 @@ impl Fairing for CacheWorkerFairing {
         // Spawn the worker task now that we're in an async context.
         spawn_worker_task(rx);
 
-        Ok(rocket.manage(tx))
+        Ok(rocket.manage(tx).manage(new_preview_cache()))
     }
 
     async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
@@ -134,6 +535,9 @@ impl Fairing for CacheWorkerFairing {
                 println!("Cache repair: No CacheSender found in managed state.");
             }
         });
+
+        // Periodically evict least-recently-accessed cache files once over budget.
+        spawn_cache_budget_janitor();
     }
 }
 
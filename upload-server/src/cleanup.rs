@@ -1,124 +1,295 @@
-use glob::glob;
+use crate::config;
+use crate::derived_manifest::{self, DerivedTree};
+use crate::metadata;
+use crate::store::{ArtifactStat, ImageStore, ImageStores};
 use std::collections::HashSet;
-use std::fs;
-use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::time;
 
 const CLEANUP_INTERVAL_SECS: u64 = 300; // 5 minutes.
 
-/// Spawns the background cleanup task.
-pub fn spawn_cleanup_task() {
-    tokio::spawn(async {
+/// Spawns the background cleanup task. Each pass runs in its own task
+/// rather than inline in the interval loop, so a pass running long (a large
+/// library, a slow backend) can't delay the timer past its next tick; if a
+/// pass is still running when the next tick fires, that tick is skipped
+/// rather than queued up to run immediately after.
+pub fn spawn_cleanup_task(stores: ImageStores) {
+    tokio::spawn(async move {
         let mut interval = time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+        interval.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        let running = Arc::new(AtomicBool::new(false));
 
         loop {
             interval.tick().await;
-            run_cleanup();
+
+            if running.swap(true, Ordering::AcqRel) {
+                println!("Cleanup: previous pass still running, skipping this tick");
+                continue;
+            }
+
+            let stores = stores.clone();
+            let running = running.clone();
+            tokio::spawn(async move {
+                run_cleanup(&stores).await;
+                running.store(false, Ordering::Release);
+            });
         }
     });
 }
 
+/// A directory's retention budget: once its survivors (after orphan
+/// removal) exceed either limit, least-recently-accessed files are evicted
+/// until both are satisfied again. `None` disables that limit.
+#[derive(Debug, Clone, Copy)]
+struct RetentionPolicy {
+    max_bytes: Option<u64>,
+    max_files: Option<usize>,
+}
+
+/// Reads a directory's retention policy from `INKY_SOUP_{NAME}_RETENTION_BYTES`
+/// and `INKY_SOUP_{NAME}_RETENTION_MAX_FILES`, falling back to `default_bytes`
+/// (file-count limiting is opt-in, since it's a less universal knob than a
+/// byte budget).
+fn retention_policy(name: &str, default_bytes: u64) -> RetentionPolicy {
+    let upper = name.to_uppercase();
+
+    let max_bytes = std::env::var(format!("INKY_SOUP_{}_RETENTION_BYTES", upper))
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&v: &u64| v > 0)
+        .or(Some(default_bytes));
+
+    let max_files = std::env::var(format!("INKY_SOUP_{}_RETENTION_MAX_FILES", upper))
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&v: &usize| v > 0);
+
+    RetentionPolicy { max_bytes, max_files }
+}
+
+/// Env var gating the perceptual-duplicate derived-artifact pass. Off by
+/// default: dropping a near-duplicate's cache/dithered/thumbs means the
+/// gallery falls back to the cluster representative's for it, which is only
+/// desirable if the caller is confident near-duplicates really are
+/// interchangeable for display purposes.
+const DEDUPE_DERIVED_ENV_VAR: &str = "INKY_SOUP_CLEANUP_DEDUPE_DERIVED";
+
+fn dedupe_derived_enabled() -> bool {
+    std::env::var(DEDUPE_DERIVED_ENV_VAR).is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+}
+
+/// Drops the derived artifacts (cache, dithered, thumbs) of every image in a
+/// perceptual-duplicate cluster except the first, since they'd render the
+/// same as the representative's. The originals themselves are untouched -
+/// only re-render work is saved, not storage of the uploads.
+async fn remove_redundant_derived_artifacts(stores: &ImageStores) {
+    for cluster in metadata::find_duplicate_clusters() {
+        let Some((representative, redundant)) = cluster.split_first() else {
+            continue;
+        };
+
+        for original in redundant {
+            let entry = derived_manifest::clear_and_save(original);
+            for (name, store, keys) in [
+                ("cache", &stores.cache, entry.cache),
+                ("dithered", &stores.dithered, entry.dithered),
+                ("thumbs", &stores.thumbs, entry.thumbs),
+            ] {
+                for key in keys {
+                    match store.delete(&key).await {
+                        Ok(()) => println!(
+                            "Dedupe: removed redundant {} artifact '{}' ({} is a near-duplicate of {})",
+                            name, key, original, representative
+                        ),
+                        Err(e) => println!("Dedupe: failed to remove {} artifact '{}': {}", name, key, e),
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// Runs a single cleanup pass.
-fn run_cleanup() {
+async fn run_cleanup(stores: &ImageStores) {
     println!("Running cleanup task...");
 
-    // Build set of valid original image filenames.
-    let originals = get_original_filenames();
-    println!("Found {} original images", originals.len());
+    if dedupe_derived_enabled() {
+        remove_redundant_derived_artifacts(stores).await;
+    }
 
-    // Clean up cache directory.
-    let cache_removed = cleanup_derived_directory("static/images/cache", &originals);
+    // Build set of valid original image filenames. `stores.originals.list()`
+    // returns every non-directory entry regardless of extension, so it's
+    // filtered through `config::is_allowed_original_filename` - the same
+    // predicate the upload path could use to validate incoming extensions -
+    // to keep newer formats (HEIF, RAW) recognized as originals and
+    // temp/hidden files from being treated as one.
+    let originals = match stores.originals.list().await {
+        Ok(names) => names
+            .into_iter()
+            .filter(|name| config::is_allowed_original_filename(name))
+            .collect::<HashSet<_>>(),
+        Err(e) => {
+            println!("Cleanup failed to list originals: {}", e);
+            return;
+        }
+    };
+    println!("Found {} original images", originals.len());
 
-    // Clean up dithered directory.
-    let dithered_removed = cleanup_derived_directory("static/images/dithered", &originals);
+    // Drop manifest entries for originals that are gone before checking
+    // what each derived store references, so a deleted original's
+    // artifacts are treated as orphaned rather than still-referenced.
+    let manifest = derived_manifest::prune_and_save(&originals);
 
-    // Clean up thumbs directory.
-    let thumbs_removed = cleanup_derived_directory("static/images/thumbs", &originals);
+    // Remove orphans first, then LRU-trim what survives each directory down
+    // to its retention budget. The three derived directories are scanned
+    // and cleaned concurrently, since they're independent stores.
+    let ((cache_removed, cache_survivors), (dithered_removed, dithered_survivors), (thumbs_removed, thumbs_survivors)) = tokio::join!(
+        cleanup_derived_store("cache", &stores.cache, &manifest.referenced_keys(DerivedTree::Cache)),
+        cleanup_derived_store("dithered", &stores.dithered, &manifest.referenced_keys(DerivedTree::Dithered)),
+        cleanup_derived_store("thumbs", &stores.thumbs, &manifest.referenced_keys(DerivedTree::Thumbs)),
+    );
 
     if cache_removed > 0 || dithered_removed > 0 || thumbs_removed > 0 {
         println!(
-            "Cleanup complete: removed {} cache, {} dithered, {} thumbs",
+            "Cleanup complete: removed {} cache, {} dithered, {} thumbs orphan(s)",
             cache_removed, dithered_removed, thumbs_removed
         );
     } else {
         println!("Cleanup complete: no orphaned files found");
     }
+
+    // 1 GiB for cache/dithered (re-renderable from the original at some
+    // cost); 256 MiB for thumbs (small, but numerous on a large library).
+    const DEFAULT_CACHE_BUDGET_BYTES: u64 = 1024 * 1024 * 1024;
+    const DEFAULT_THUMBS_BUDGET_BYTES: u64 = 256 * 1024 * 1024;
+
+    tokio::join!(
+        enforce_retention("cache", &stores.cache, cache_survivors, retention_policy("CACHE", DEFAULT_CACHE_BUDGET_BYTES)),
+        enforce_retention(
+            "dithered",
+            &stores.dithered,
+            dithered_survivors,
+            retention_policy("DITHERED", DEFAULT_CACHE_BUDGET_BYTES),
+        ),
+        enforce_retention(
+            "thumbs",
+            &stores.thumbs,
+            thumbs_survivors,
+            retention_policy("THUMBS", DEFAULT_THUMBS_BUDGET_BYTES),
+        ),
+    );
 }
 
-/// Gets the set of original image filenames (without path).
-fn get_original_filenames() -> HashSet<String> {
-    let mut filenames = HashSet::new();
+/// Cleans up a derived store (cache, dithered, or thumbs).
+/// Removes any key the manifest doesn't reference for this tree, rather
+/// than reconstructing an original name by pattern-matching the key - that
+/// lets multiple dither variants or thumbnail sizes per original coexist
+/// without cleanup mistaking them for orphans.
+///
+/// Orphan deletes run concurrently (one task per key) rather than one at a
+/// time, since a large library can have thousands of entries in a single
+/// directory; counts are collected through atomics since they're written
+/// from those concurrent tasks.
+/// Returns the number of keys removed and the keys that survived.
+async fn cleanup_derived_store(name: &'static str, store: &Arc<dyn ImageStore>, referenced: &HashSet<String>) -> (usize, Vec<String>) {
+    let keys = match store.list().await {
+        Ok(keys) => keys,
+        Err(e) => {
+            println!("Cleanup failed to list {} store: {}", name, e);
+            return (0, Vec::new());
+        }
+    };
 
-    for entry in glob("static/images/*").unwrap_or_else(|_| panic!("Failed to read glob pattern")) {
-        if let Ok(path) = entry {
-            // Skip directories and metadata file.
-            if path.is_dir() || path.extension().map(|e| e == "json").unwrap_or(false) {
-                continue;
-            }
+    let removed = Arc::new(AtomicUsize::new(0));
+    let survivors = Arc::new(Mutex::new(Vec::new()));
+    let mut tasks = Vec::new();
 
-            if let Some(filename) = path.file_name().and_then(|f| f.to_str()) {
-                filenames.insert(filename.to_string());
-            }
+    for key in keys {
+        if referenced.contains(&key) {
+            survivors.lock().unwrap().push(key);
+            continue;
         }
+
+        let store = store.clone();
+        let removed = removed.clone();
+        let survivors = survivors.clone();
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = store.delete(&key).await {
+                println!("Failed to remove {} key '{}': {}", name, key, e);
+                survivors.lock().unwrap().push(key);
+            } else {
+                println!("Removed orphaned {} key (not in manifest): {}", name, key);
+                removed.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
     }
 
-    filenames
-}
+    for task in tasks {
+        let _ = task.await;
+    }
 
-/// Cleans up a derived directory (cache or dithered).
-/// Removes files that:
-/// - Don't end in .png
-/// - Don't have a corresponding original image
-/// Returns the number of files removed.
-fn cleanup_derived_directory(dir_path: &str, originals: &HashSet<String>) -> usize {
-    let mut removed = 0;
-    let pattern = format!("{}/*", dir_path);
-
-    for entry in glob(&pattern).unwrap_or_else(|_| panic!("Failed to read glob pattern")) {
-        if let Ok(path) = entry {
-            if path.is_dir() {
-                continue;
-            }
+    let removed = removed.load(Ordering::Relaxed);
+    let survivors = Arc::try_unwrap(survivors).expect("all delete tasks joined").into_inner().unwrap();
+    (removed, survivors)
+}
 
-            let should_remove = should_remove_derived_file(&path, originals);
+/// Evicts least-recently-accessed keys from `survivors` until `policy` is
+/// satisfied. Keys here are the ones the orphan pass kept: those with a live
+/// original, plus any orphan whose delete failed. Eviction is purely a
+/// space/count budget, not a correctness cleanup.
+async fn enforce_retention(name: &str, store: &Arc<dyn ImageStore>, survivors: Vec<String>, policy: RetentionPolicy) {
+    if policy.max_bytes.is_none() && policy.max_files.is_none() {
+        return;
+    }
 
-            if should_remove {
-                if let Err(e) = fs::remove_file(&path) {
-                    println!("Failed to remove {}: {}", path.display(), e);
-                } else {
-                    println!("Removed orphaned file: {}", path.display());
-                    removed += 1;
-                }
-            }
+    let mut entries: Vec<(String, ArtifactStat)> = Vec::new();
+    for key in survivors {
+        match store.stat(&key).await {
+            Ok(stat) => entries.push((key, stat)),
+            Err(e) => println!("Retention: failed to stat {} key '{}': {}", name, key, e),
         }
     }
 
-    removed
-}
+    let total_bytes: u64 = entries.iter().map(|(_, stat)| stat.size).sum();
+    let over_budget = policy.max_bytes.is_some_and(|max| total_bytes > max);
+    let over_count = policy.max_files.is_some_and(|max| entries.len() > max);
 
-/// Determines if a derived file should be removed.
-fn should_remove_derived_file(path: &Path, originals: &HashSet<String>) -> bool {
-    let filename = match path.file_name().and_then(|f| f.to_str()) {
-        Some(f) => f,
-        None => return true, // Invalid filename.
-    };
-
-    // Must be a .png file.
-    if !filename.ends_with(".png") {
-        println!("Orphaned (not .png): {}", path.display());
-        return true;
+    if !over_budget && !over_count {
+        return;
     }
 
-    // Extract original filename by removing the .png suffix.
-    // Cache/dithered files are named "{original}.png", so "photo.jpg.png" -> "photo.jpg".
-    let original_name = &filename[..filename.len() - 4];
+    // Oldest-accessed first.
+    entries.sort_by_key(|(_, stat)| stat.last_accessed);
+
+    let mut remaining_bytes = total_bytes;
+    let mut remaining_count = entries.len();
+    let mut reclaimed = 0u64;
+    let mut removed = 0usize;
 
-    // Check if original exists.
-    if !originals.contains(original_name) {
-        println!("Orphaned (no original '{}'): {}", original_name, path.display());
-        return true;
+    for (key, stat) in entries {
+        let still_over_budget = policy.max_bytes.is_some_and(|max| remaining_bytes > max);
+        let still_over_count = policy.max_files.is_some_and(|max| remaining_count > max);
+        if !still_over_budget && !still_over_count {
+            break;
+        }
+
+        match store.delete(&key).await {
+            Ok(()) => {
+                remaining_bytes -= stat.size;
+                remaining_count -= 1;
+                reclaimed += stat.size;
+                removed += 1;
+            }
+            Err(e) => println!("Retention: failed to evict {} key '{}': {}", name, key, e),
+        }
     }
 
-    false
+    if removed > 0 {
+        println!(
+            "Retention ({}): evicted {} file(s), reclaimed {} bytes",
+            name, removed, reclaimed
+        );
+    }
 }
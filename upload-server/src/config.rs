@@ -11,6 +11,45 @@ pub static IMAGES_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
     PathBuf::from(env::var("INKY_SOUP_IMAGES_DIR").unwrap_or_else(|_| "static/images".to_string()))
 });
 
+/// Filesystem directory the `FileServer` in `main.rs` is mounted from, i.e.
+/// the prefix to strip from a filesystem path to get its URL.
+/// Set via `INKY_SOUP_STATIC_MOUNT_PREFIX`, defaults to `static`.
+pub static STATIC_MOUNT_PREFIX: LazyLock<PathBuf> = LazyLock::new(|| {
+    PathBuf::from(env::var("INKY_SOUP_STATIC_MOUNT_PREFIX").unwrap_or_else(|_| "static".to_string()))
+});
+
+/// Path to the display/upload configuration file, shared by every reader of
+/// physical display properties (`main.rs`'s display-config and upload-limits
+/// endpoints, and `dither`'s server-side fallback render).
+pub const DISPLAY_CONFIG_PATH: &str = "/etc/inky-soup/display.conf";
+
+/// Reads `path` as a simple `KEY = value` config file, skipping blank lines
+/// and `#` comments.
+pub fn read_config_pairs(path: &str) -> Vec<(String, String)> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
+
+/// Reads the configured display color mode ("multi" by default), used
+/// wherever a palette needs to be picked outside an HTTP request handler
+/// (e.g. `dither`'s flash-worker fallback render).
+pub fn display_color_mode() -> String {
+    read_config_pairs(DISPLAY_CONFIG_PATH)
+        .into_iter()
+        .find(|(key, _)| key == "DISPLAY_COLOR")
+        .map(|(_, value)| value)
+        .unwrap_or_else(|| "multi".to_string())
+}
+
 /// Base directory for writable runtime data.
 /// Set via `INKY_SOUP_DATA_DIR`, defaults to parent of `IMAGES_DIR` when possible.
 pub fn data_dir() -> PathBuf {
@@ -61,6 +100,11 @@ pub fn display_runtime_settings_path() -> PathBuf {
     settings_dir().join("display-runtime.json")
 }
 
+/// Path to the derived-artifact manifest (see `derived_manifest`).
+pub fn derived_manifest_path() -> PathBuf {
+    settings_dir().join("derived-manifest.json")
+}
+
 /// Get the full path for a cached image.
 pub fn cache_path(filename: &str) -> PathBuf {
     cache_dir().join(format!("{}.png", filename))
@@ -81,6 +125,40 @@ pub fn original_path(filename: &str) -> PathBuf {
     IMAGES_DIR.join(filename)
 }
 
+/// A derived asset's filesystem path and the URL used to fetch it, computed
+/// together so handlers and templates never have to re-derive one from the
+/// other (e.g. by stripping prefixes off a stringified path).
+#[derive(Debug, Clone)]
+pub struct AssetRef {
+    pub fs_path: PathBuf,
+    pub url: String,
+}
+
+fn asset_ref(fs_path: PathBuf) -> AssetRef {
+    let url = url_path(&fs_path);
+    AssetRef { fs_path, url }
+}
+
+/// `AssetRef` for an image's cached display-resolution PNG.
+pub fn cache_asset(filename: &str) -> AssetRef {
+    asset_ref(cache_path(filename))
+}
+
+/// `AssetRef` for an image's gallery thumbnail.
+pub fn thumb_asset(filename: &str) -> AssetRef {
+    asset_ref(thumb_path(filename))
+}
+
+/// `AssetRef` for an image's pre-dithered upload.
+pub fn dithered_asset(filename: &str) -> AssetRef {
+    asset_ref(dithered_path(filename))
+}
+
+/// `AssetRef` for an original uploaded image.
+pub fn original_asset(filename: &str) -> AssetRef {
+    asset_ref(original_path(filename))
+}
+
 /// Get all directories that need to exist for the server to function.
 pub fn required_dirs() -> Vec<PathBuf> {
     vec![
@@ -93,17 +171,100 @@ pub fn required_dirs() -> Vec<PathBuf> {
     ]
 }
 
-/// Get the URL path for serving an image (relative to static mount).
-/// This strips the "static/" prefix if present for URL generation.
+/// Default allowed extensions (without the leading dot, case-insensitive)
+/// for files in `IMAGES_DIR` treated as originals. Covers what the decode
+/// pipeline already handles (JPEG/PNG/WebP) plus HEIF and common RAW
+/// formats, so a phone or camera upload isn't mistaken for a stray
+/// non-image file.
+const DEFAULT_ALLOWED_ORIGINAL_EXTENSIONS: &[&str] =
+    &["jpg", "jpeg", "png", "webp", "heic", "heif", "cr2", "nef", "arw", "dng", "raf", "orf"];
+
+/// Reads a comma-separated extension list from an env var, normalized to
+/// lowercase with any leading dots stripped.
+fn read_extension_list(var: &str) -> Option<Vec<String>> {
+    env::var(var).ok().map(|raw| {
+        raw.split(',')
+            .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect()
+    })
+}
+
+/// Lowercased extension of `filename`, without the dot. Empty if there is
+/// none.
+fn extension_of(filename: &str) -> String {
+    filename.rsplit_once('.').map(|(_, ext)| ext.to_lowercase()).unwrap_or_default()
+}
+
+/// Whether `filename` should be treated as an original image by the cleanup
+/// scan and its orphan test - the single predicate both route through, so
+/// they can't drift into disagreeing about what counts as an original. The
+/// gallery listing in `main.rs` does not use it. Hidden files (leading `.`) are never originals, regardless of
+/// extension.
+///
+/// Extensions are configured via `INKY_SOUP_ORIGINAL_ALLOWED_EXTENSIONS` and
+/// `INKY_SOUP_ORIGINAL_EXCLUDED_EXTENSIONS` (both comma-separated,
+/// case-insensitive, dots optional), falling back to
+/// `DEFAULT_ALLOWED_ORIGINAL_EXTENSIONS` when the allow list isn't set. The
+/// exclusion list is checked first, so it can carve out a partial-upload
+/// suffix like `tmp` even if it's added to the allow list by mistake.
+pub fn is_allowed_original_filename(filename: &str) -> bool {
+    if filename.starts_with('.') {
+        return false;
+    }
+
+    let ext = extension_of(filename);
+    if ext.is_empty() {
+        return false;
+    }
+
+    if let Some(excluded) = read_extension_list("INKY_SOUP_ORIGINAL_EXCLUDED_EXTENSIONS") {
+        if excluded.contains(&ext) {
+            return false;
+        }
+    }
+
+    let allowed = read_extension_list("INKY_SOUP_ORIGINAL_ALLOWED_EXTENSIONS")
+        .unwrap_or_else(|| DEFAULT_ALLOWED_ORIGINAL_EXTENSIONS.iter().map(|s| s.to_string()).collect());
+
+    allowed.contains(&ext)
+}
+
+/// Get the URL path for serving a file (relative to the static mount).
+/// Strips `STATIC_MOUNT_PREFIX` as a path component, not by scanning the
+/// stringified path for substrings, so it doesn't get confused by a
+/// filename that happens to contain "static/" or "images/".
 pub fn url_path(fs_path: &PathBuf) -> String {
-    let path_str = fs_path.to_string_lossy();
-    if path_str.starts_with("static/") {
-        path_str.strip_prefix("static/").unwrap().to_string()
-    } else {
-        // For absolute paths, return just the images/... portion.
-        path_str
-            .find("images/")
-            .map(|i| path_str[i..].to_string())
-            .unwrap_or_else(|| path_str.to_string())
+    fs_path
+        .strip_prefix(STATIC_MOUNT_PREFIX.as_path())
+        .unwrap_or(fs_path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // These only exercise the default (no env var) path - setting the
+    // allow/deny env vars here would race with other tests in the same
+    // process, since `std::env::var` is process-global.
+
+    #[test]
+    fn test_allows_default_image_extensions() {
+        assert!(is_allowed_original_filename("photo.jpg"));
+        assert!(is_allowed_original_filename("photo.JPEG"));
+        assert!(is_allowed_original_filename("photo.heic"));
+        assert!(is_allowed_original_filename("photo.DNG"));
+    }
+
+    #[test]
+    fn test_rejects_non_image_and_hidden_files() {
+        assert!(!is_allowed_original_filename("metadata.json"));
+        assert!(!is_allowed_original_filename("notes.txt"));
+        assert!(!is_allowed_original_filename(".hidden"));
+        assert!(!is_allowed_original_filename("no-extension"));
     }
 }
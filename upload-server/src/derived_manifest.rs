@@ -0,0 +1,231 @@
+//! Manifest sidecar tracking which derived artifacts (cache, dithered,
+//! thumbs) exist for each original image.
+//!
+//! Suffix-matching ("photo.jpg.png" -> "photo.jpg") can't tell multiple
+//! dither variants or thumbnail sizes for the same original apart, and
+//! breaks the moment the naming scheme changes. This manifest is the
+//! source of truth `cleanup` uses instead: it's updated wherever a derived
+//! artifact is written, and read back (rather than reconstructed by
+//! pattern-matching keys) when deciding what's still referenced.
+
+use crate::config;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{LazyLock, Mutex};
+
+/// Serializes every load-modify-save sequence against the manifest file.
+/// `register`/`prune_and_save`/`clear_and_save` are each individually atomic
+/// against the file (write-via-temp-then-rename), but are called
+/// concurrently for *different* originals (from `cache_worker`'s bounded
+/// worker pool, `dither`, and several handlers in `main.rs`), and an
+/// uncoordinated load there then save there can still race: both read the
+/// manifest before either writes it back, so the second save silently
+/// clobbers the first's change. Holding this lock for the whole
+/// load-modify-save sequence serializes those callers instead.
+static MANIFEST_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// Which derived tree an artifact key belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerivedTree {
+    Cache,
+    Dithered,
+    Thumbs,
+}
+
+/// The derived artifact keys recorded for one original image, one set per
+/// derived tree. A `HashSet` rather than a single key so multiple dither
+/// variants or thumbnail sizes for the same original can coexist.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DerivedEntry {
+    #[serde(default)]
+    pub cache: HashSet<String>,
+    #[serde(default)]
+    pub dithered: HashSet<String>,
+    #[serde(default)]
+    pub thumbs: HashSet<String>,
+}
+
+/// Maps each original filename to the derived artifacts produced from it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DerivedManifest {
+    #[serde(default)]
+    entries: HashMap<String, DerivedEntry>,
+}
+
+impl DerivedManifest {
+    /// Every key in `tree` this manifest references, across all originals.
+    pub fn referenced_keys(&self, tree: DerivedTree) -> HashSet<String> {
+        self.entries
+            .values()
+            .flat_map(|entry| tree_set(entry, tree).iter())
+            .cloned()
+            .collect()
+    }
+
+    /// Drops manifest entries whose original is no longer in `originals`,
+    /// returning the dropped originals' filenames.
+    pub fn prune_missing_originals(&mut self, originals: &HashSet<String>) -> Vec<String> {
+        let missing: Vec<String> = self.entries.keys().filter(|name| !originals.contains(*name)).cloned().collect();
+
+        for name in &missing {
+            self.entries.remove(name);
+        }
+
+        missing
+    }
+}
+
+fn tree_set(entry: &DerivedEntry, tree: DerivedTree) -> &HashSet<String> {
+    match tree {
+        DerivedTree::Cache => &entry.cache,
+        DerivedTree::Dithered => &entry.dithered,
+        DerivedTree::Thumbs => &entry.thumbs,
+    }
+}
+
+fn tree_set_mut(entry: &mut DerivedEntry, tree: DerivedTree) -> &mut HashSet<String> {
+    match tree {
+        DerivedTree::Cache => &mut entry.cache,
+        DerivedTree::Dithered => &mut entry.dithered,
+        DerivedTree::Thumbs => &mut entry.thumbs,
+    }
+}
+
+fn load_from_path(path: &Path) -> DerivedManifest {
+    if !path.exists() {
+        return DerivedManifest::default();
+    }
+
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+            warn!("Failed to parse derived manifest '{}': {}. Starting fresh.", path.display(), e);
+            DerivedManifest::default()
+        }),
+        Err(e) => {
+            warn!("Failed to read derived manifest '{}': {}. Starting fresh.", path.display(), e);
+            DerivedManifest::default()
+        }
+    }
+}
+
+fn save_to_path(path: &Path, manifest: &DerivedManifest) -> Result<(), String> {
+    let parent = path.parent().ok_or_else(|| format!("Invalid manifest path: {}", path.display()))?;
+
+    fs::create_dir_all(parent).map_err(|e| format!("Failed to create manifest directory '{}': {}", parent.display(), e))?;
+
+    let json = serde_json::to_string_pretty(manifest).map_err(|e| format!("Failed to serialize derived manifest: {}", e))?;
+
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, json).map_err(|e| format!("Failed to write temp derived manifest '{}': {}", temp_path.display(), e))?;
+    fs::rename(&temp_path, path).map_err(|e| format!("Failed to atomically persist derived manifest '{}': {}", path.display(), e))?;
+
+    Ok(())
+}
+
+/// Loads the derived-artifact manifest from writable app data storage.
+pub fn load() -> DerivedManifest {
+    load_from_path(&config::derived_manifest_path())
+}
+
+fn save(manifest: &DerivedManifest) -> Result<(), String> {
+    save_to_path(&config::derived_manifest_path(), manifest)
+}
+
+/// Drops manifest entries whose original is gone and persists the result,
+/// returning the pruned manifest for the caller to check referenced keys
+/// against. Called once per cleanup pass, before any derived files are
+/// removed.
+pub fn prune_and_save(originals: &HashSet<String>) -> DerivedManifest {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let mut manifest = load();
+    let dropped = manifest.prune_missing_originals(originals);
+
+    if !dropped.is_empty() {
+        if let Err(e) = save(&manifest) {
+            warn!("Failed to persist derived manifest after pruning {} missing original(s): {}", dropped.len(), e);
+        }
+    }
+
+    manifest
+}
+
+/// Records that `key` now exists in `tree` for `original`. Read-modify-write
+/// against the on-disk manifest; `register` is called concurrently for
+/// different originals from `cache_worker`'s worker pool, `dither`, and
+/// several handlers in `main.rs`, so the whole sequence runs under
+/// `MANIFEST_LOCK` - without it, two concurrent calls for different
+/// originals can both load before either saves, and the second save would
+/// silently drop the first's entry.
+pub fn register(original: &str, tree: DerivedTree, key: &str) {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let mut manifest = load();
+    let entry = manifest.entries.entry(original.to_string()).or_default();
+    tree_set_mut(entry, tree).insert(key.to_string());
+
+    if let Err(e) = save(&manifest) {
+        warn!("Failed to persist derived manifest after registering {} ({:?}): {}", original, tree, e);
+    }
+}
+
+/// Removes and returns `original`'s manifest entry, persisting the result.
+/// Used by `cleanup`'s perceptual-duplicate pass: once an original's derived
+/// artifacts are deleted in favor of a cluster representative's, dropping
+/// its entry here is what lets it regenerate on demand (e.g. a later
+/// `/api/render`) rather than being treated as still up to date.
+pub fn clear_and_save(original: &str) -> DerivedEntry {
+    let _guard = MANIFEST_LOCK.lock().unwrap();
+    let mut manifest = load();
+    let entry = manifest.entries.remove(original).unwrap_or_default();
+
+    if let Err(e) = save(&manifest) {
+        warn!("Failed to persist derived manifest after clearing {}: {}", original, e);
+    }
+
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn unique_temp_path(prefix: &str) -> std::path::PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        std::env::temp_dir().join(format!("{}_{}_{}.json", prefix, std::process::id(), nanos))
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let path = unique_temp_path("inky_soup_derived_manifest");
+        let mut manifest = DerivedManifest::default();
+        manifest
+            .entries
+            .entry("photo.jpg".to_string())
+            .or_default()
+            .cache
+            .insert("photo.jpg.png".to_string());
+
+        save_to_path(&path, &manifest).unwrap();
+        let loaded = load_from_path(&path);
+        assert!(loaded.referenced_keys(DerivedTree::Cache).contains("photo.jpg.png"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_prune_missing_originals() {
+        let mut manifest = DerivedManifest::default();
+        manifest.entries.entry("gone.jpg".to_string()).or_default();
+        manifest.entries.entry("still-here.jpg".to_string()).or_default();
+
+        let originals: HashSet<String> = ["still-here.jpg".to_string()].into_iter().collect();
+        let dropped = manifest.prune_missing_originals(&originals);
+
+        assert_eq!(dropped, vec!["gone.jpg".to_string()]);
+        assert!(manifest.entries.contains_key("still-here.jpg"));
+        assert!(!manifest.entries.contains_key("gone.jpg"));
+    }
+}
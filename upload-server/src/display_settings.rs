@@ -41,16 +41,64 @@ pub fn parse_rotation_degrees(rotation_degrees: i32) -> Option<u16> {
     }
 }
 
-/// Computes logical dimensions from physical dimensions and rotation.
-/// 90/270 swap width and height. 0/180 keep dimensions unchanged.
+/// Whether a normalized image needs to be mirrored after it's rotated.
+/// EXIF orientations 2, 4, 5, and 7 all involve a mirror; see
+/// `exif_rotation_and_flip`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flip {
+    None,
+    Horizontal,
+    Vertical,
+}
+
+/// Decomposes an EXIF `Orientation` tag value (1-8) into the clockwise
+/// rotation and mirror needed to normalize an image to upright, matching
+/// `cache_worker::apply_exif_orientation`'s transform for each value.
+/// Unknown/missing values (anything outside 1-8) are treated as identity.
+fn exif_rotation_and_flip(exif_orientation: u8) -> (u16, Flip) {
+    match exif_orientation {
+        2 => (0, Flip::Horizontal),
+        3 => (180, Flip::None),
+        4 => (0, Flip::Vertical),
+        5 => (90, Flip::Horizontal),
+        6 => (90, Flip::None),
+        7 => (270, Flip::Horizontal),
+        8 => (270, Flip::None),
+        _ => (0, Flip::None),
+    }
+}
+
+/// Computes the total rotation and mirror needed to take a raw original
+/// (as EXIF describes it) to an upright image on the physically mounted
+/// panel, so the gallery preview and the flashed output agree regardless of
+/// which device the photo came from. Combines the EXIF-implied rotation
+/// with the mount's counter-rotation (`compute_flash_rotation_degrees`);
+/// the mount rotation never introduces a mirror of its own.
+pub fn compute_effective_rotation(exif_orientation: u8, mount_rotation_degrees: u16) -> (u16, Flip) {
+    let (exif_rotation, flip) = exif_rotation_and_flip(exif_orientation);
+    let mount_compensation = compute_flash_rotation_degrees(mount_rotation_degrees);
+    let total_rotation = (exif_rotation + mount_compensation) % 360;
+    (total_rotation, flip)
+}
+
+/// Computes logical dimensions from physical dimensions, the source
+/// image's EXIF orientation, and the panel's mount rotation. A 90/270
+/// swap from either the EXIF orientation or the mount rotation swaps
+/// width and height; swapping twice (one from each) cancels out.
 pub fn compute_logical_dimensions(
     physical_width: u32,
     physical_height: u32,
+    exif_orientation: u8,
     rotation_degrees: u16,
 ) -> (u32, u32) {
-    match rotation_degrees {
-        90 | 270 => (physical_height, physical_width),
-        _ => (physical_width, physical_height),
+    let (exif_rotation, _) = exif_rotation_and_flip(exif_orientation);
+    let exif_swaps = matches!(exif_rotation, 90 | 270);
+    let mount_swaps = matches!(rotation_degrees, 90 | 270);
+
+    if exif_swaps != mount_swaps {
+        (physical_height, physical_width)
+    } else {
+        (physical_width, physical_height)
     }
 }
 
@@ -226,10 +274,32 @@ mod tests {
 
     #[test]
     fn test_logical_dimensions_swap_for_90_and_270() {
-        assert_eq!(compute_logical_dimensions(1600, 1200, 0), (1600, 1200));
-        assert_eq!(compute_logical_dimensions(1600, 1200, 180), (1600, 1200));
-        assert_eq!(compute_logical_dimensions(1600, 1200, 90), (1200, 1600));
-        assert_eq!(compute_logical_dimensions(1600, 1200, 270), (1200, 1600));
+        assert_eq!(compute_logical_dimensions(1600, 1200, 1, 0), (1600, 1200));
+        assert_eq!(compute_logical_dimensions(1600, 1200, 1, 180), (1600, 1200));
+        assert_eq!(compute_logical_dimensions(1600, 1200, 1, 90), (1200, 1600));
+        assert_eq!(compute_logical_dimensions(1600, 1200, 1, 270), (1200, 1600));
+    }
+
+    #[test]
+    fn test_logical_dimensions_account_for_exif_swap() {
+        // EXIF orientation 6 (rotate 90 CW) swaps dimensions on its own.
+        assert_eq!(compute_logical_dimensions(1600, 1200, 6, 0), (1200, 1600));
+        // A mount rotation that also swaps cancels the EXIF swap back out.
+        assert_eq!(compute_logical_dimensions(1600, 1200, 6, 90), (1600, 1200));
+        // Orientation 3 (rotate 180) never swaps dimensions.
+        assert_eq!(compute_logical_dimensions(1600, 1200, 3, 90), (1200, 1600));
+    }
+
+    #[test]
+    fn test_effective_rotation_combines_exif_and_mount() {
+        // Identity orientation: effective rotation is just the mount's counter-rotation.
+        assert_eq!(compute_effective_rotation(1, 90), (270, Flip::None));
+        // Orientation 6 (rotate 90 CW) adds on top of the mount's counter-rotation.
+        assert_eq!(compute_effective_rotation(6, 0), (90, Flip::None));
+        assert_eq!(compute_effective_rotation(6, 90), (360 % 360, Flip::None));
+        // Mirrored orientations carry their flip through untouched by mount rotation.
+        assert_eq!(compute_effective_rotation(2, 0), (0, Flip::Horizontal));
+        assert_eq!(compute_effective_rotation(5, 180), (270, Flip::Horizontal));
     }
 
     #[test]
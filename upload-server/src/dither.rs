@@ -0,0 +1,90 @@
+//! Server-side fallback for producing a flashable dithered image.
+//!
+//! `submit_flash_image` used to hard-require that the browser had already
+//! uploaded a pre-dithered PNG to `dithered_path`, which broke API-only
+//! clients and re-flashes after a cache purge. `ensure_dithered` lets the
+//! flash worker render one itself from the original, reusing the same
+//! `processor::render` pipeline `/api/render` uses, with the image's saved
+//! filter/saturation/brightness/contrast/dither-algorithm settings.
+//!
+//! The original and the rendered result both go through `stores` rather
+//! than local paths directly, so this works the same whether originals live
+//! on the SD card or in S3. `dithered_path` is still a local filesystem
+//! path, since the flasher subprocess needs one to read regardless of
+//! backend; it's treated purely as a local cache location for whatever the
+//! store holds under `filename`'s dithered key.
+
+use crate::derived_manifest::{self, DerivedTree};
+use crate::store::ImageStores;
+use crate::{cache_worker, config, metadata, processor};
+use std::path::Path;
+
+/// Ensures a dithered image exists at `dithered_path` for `filename`,
+/// rendering one from the original if it's missing or `force` is set.
+/// Runs the decode/resize/dither work on a blocking thread, since none of
+/// it is async.
+pub async fn ensure_dithered(
+    filename: &str,
+    dithered_path: &str,
+    force: bool,
+    stores: &ImageStores,
+) -> Result<(), String> {
+    let dithered_key = format!("{}.png", filename);
+
+    if !force && stores.dithered.exists(&dithered_key).await {
+        // Already rendered. Make sure a local copy exists for the flasher
+        // subprocess to read - the store backend may not keep one on disk
+        // (e.g. S3), or a local cache purge may have removed it.
+        if !Path::new(dithered_path).exists() {
+            let bytes = stores.dithered.get(&dithered_key).await?;
+            tokio::fs::write(dithered_path, bytes)
+                .await
+                .map_err(|e| format!("Failed to cache dithered image locally: {}", e))?;
+        }
+        return Ok(());
+    }
+
+    let original_bytes = stores.originals.get(filename).await?;
+
+    let render_filename = filename.to_string();
+    let color_mode = config::display_color_mode();
+    let png_bytes = tokio::task::spawn_blocking(move || render(&render_filename, &original_bytes, &color_mode))
+        .await
+        .map_err(|e| format!("dither task panicked: {}", e))??;
+
+    stores.dithered.put(&dithered_key, png_bytes.clone()).await?;
+    derived_manifest::register(filename, DerivedTree::Dithered, &dithered_key);
+
+    tokio::fs::write(dithered_path, png_bytes)
+        .await
+        .map_err(|e| format!("Failed to cache dithered image locally: {}", e))
+}
+
+/// Renders `filename`'s original image bytes to a dithered PNG using its
+/// saved render settings, matching `/api/render`'s pipeline exactly so a
+/// server-side fallback render looks the same as one the browser requested.
+fn render(filename: &str, original_bytes: &[u8], color_mode: &str) -> Result<Vec<u8>, String> {
+    let img = cache_worker::open_oriented_bytes(original_bytes)?;
+
+    let settings = metadata::get_all_metadata(filename);
+    let resize_filter = metadata::parse_filter(&settings.filter);
+    let resized = img
+        .resize_exact(cache_worker::DISPLAY_WIDTH, cache_worker::DISPLAY_HEIGHT, resize_filter)
+        .to_rgb8();
+
+    let palette = processor::palette_for(color_mode);
+    let rendered = processor::render(
+        &resized,
+        palette,
+        settings.saturation,
+        settings.brightness,
+        settings.contrast,
+        &settings.dither_algorithm,
+    );
+
+    let mut bytes = Vec::new();
+    rendered
+        .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode dithered image: {}", e))?;
+    Ok(bytes)
+}
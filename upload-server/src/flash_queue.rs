@@ -4,36 +4,159 @@
 //! This allows the HTTP endpoint to return immediately while the actual
 //! flashing happens asynchronously.
 
-use log::{debug, error, info};
-use rocket::serde::Serialize;
+use crate::dither;
+use crate::store::ImageStores;
+use log::{debug, error, info, warn};
+use rocket::serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use tokio::process::Command;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex};
 use tokio::time;
 
 /// Shared flash queue state type.
 pub type FlashQueueState = Arc<Mutex<FlashQueue>>;
 
+/// Holds the background worker's join handle so a Rocket shutdown fairing
+/// can await a graceful drain. Populated once `spawn_flash_worker` runs.
+pub type FlashWorkerHandle = Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>;
+
 /// Status of a flash job.
-#[derive(Debug, Clone, Serialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(crate = "rocket::serde")]
 pub enum FlashJobStatus {
     /// Job is waiting in queue.
     Queued,
     /// Job is currently being flashed to display.
     Flashing,
+    /// Job failed a previous attempt and is waiting for its backoff delay
+    /// to elapse before it's eligible to be dequeued again.
+    Retrying,
     /// Job completed successfully.
     Completed,
     /// Job failed with error.
     Failed,
 }
 
+/// A state transition broadcast over a [`FlashQueue`]'s event channel, so
+/// `/api/flash/events` can push updates to clients instead of them polling
+/// `/api/flash/status` in a loop.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(tag = "type")]
+pub enum FlashEvent {
+    /// A job was added to (or refreshed in) the queue.
+    Enqueued { job: FlashJob },
+    /// The worker popped a job from the queue and is now working on it.
+    Started { job: FlashJob },
+    /// The currently-flashing job entered a new phase, e.g. "dithering",
+    /// "flashing", or "second-flash".
+    Phase { job_id: u64, phase: String },
+    /// A flash attempt failed but the job is eligible for another attempt.
+    Retrying { job: FlashJob },
+    /// A job finished successfully.
+    Completed { job: FlashJob },
+    /// A job failed permanently (retries exhausted, or failed fast).
+    Failed { job: FlashJob },
+}
+
+/// Bounds how many unconsumed events a slow SSE subscriber can fall behind
+/// before older ones are dropped (`broadcast::error::RecvError::Lagged`).
+const FLASH_EVENTS_CHANNEL_CAPACITY: usize = 256;
+
 const FINISHED_JOB_RETENTION_MS: u64 = 30_000;
 
+/// Default number of attempts (including the first) before a job is given
+/// up on and moved to `Failed` permanently.
+const DEFAULT_MAX_FLASH_RETRIES: u32 = 3;
+/// Base delay for the first retry; doubles on each subsequent attempt.
+const DEFAULT_FLASH_RETRY_BASE_DELAY_MS: u64 = 1_000;
+/// Upper bound on the exponential backoff delay.
+const DEFAULT_FLASH_RETRY_MAX_DELAY_MS: u64 = 30_000;
+
+/// Can be overridden via FLASH_MAX_RETRIES for testing or flakier hardware.
+fn get_max_flash_retries() -> u32 {
+    std::env::var("FLASH_MAX_RETRIES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_FLASH_RETRIES)
+}
+
+/// Can be overridden via FLASH_RETRY_BASE_DELAY_MS.
+fn get_flash_retry_base_delay_ms() -> u64 {
+    std::env::var("FLASH_RETRY_BASE_DELAY_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_FLASH_RETRY_BASE_DELAY_MS)
+}
+
+/// Can be overridden via FLASH_RETRY_MAX_DELAY_MS.
+fn get_flash_retry_max_delay_ms() -> u64 {
+    std::env::var("FLASH_RETRY_MAX_DELAY_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_FLASH_RETRY_MAX_DELAY_MS)
+}
+
+/// Computes the exponential backoff delay for the attempt that just failed
+/// (1-indexed), capped at `get_flash_retry_max_delay_ms()`.
+fn backoff_delay_ms(failed_attempt: u32) -> u64 {
+    let shift = failed_attempt.saturating_sub(1).min(32);
+    let delay = get_flash_retry_base_delay_ms().saturating_mul(1u64 << shift);
+    delay.min(get_flash_retry_max_delay_ms())
+}
+
+/// How long a `Flashing` job can go without a heartbeat before it's
+/// considered stuck (worker died, subprocess wedged, etc.) and recovered.
+const DEFAULT_FLASH_STALE_TIMEOUT_MS: u64 = 60_000;
+/// How often the worker refreshes the current job's heartbeat while
+/// `execute_flash` is in flight.
+const DEFAULT_FLASH_HEARTBEAT_INTERVAL_MS: u64 = 5_000;
+
+/// Can be overridden via FLASH_STALE_TIMEOUT_MS.
+fn get_flash_stale_timeout_ms() -> u64 {
+    std::env::var("FLASH_STALE_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_FLASH_STALE_TIMEOUT_MS)
+}
+
+/// Can be overridden via FLASH_HEARTBEAT_INTERVAL_MS.
+fn get_flash_heartbeat_interval_ms() -> u64 {
+    std::env::var("FLASH_HEARTBEAT_INTERVAL_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_FLASH_HEARTBEAT_INTERVAL_MS)
+}
+
+/// Hard upper bound on a single `inky-soup-update-display` invocation
+/// before it's killed and treated as a failure.
+const DEFAULT_FLASH_TIMEOUT_MS: u64 = 60_000;
+/// Flashes slower than this get a `warn!` logged while still in flight, well
+/// before the hard timeout, to help diagnose slow SPI or oversized images.
+const DEFAULT_FLASH_WARN_THRESHOLD_MS: u64 = 10_000;
+
+/// Can be overridden via FLASH_TIMEOUT_MS.
+fn get_flash_timeout_ms() -> u64 {
+    std::env::var("FLASH_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_FLASH_TIMEOUT_MS)
+}
+
+/// Can be overridden via FLASH_WARN_THRESHOLD_MS.
+fn get_flash_warn_threshold_ms() -> u64 {
+    std::env::var("FLASH_WARN_THRESHOLD_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_FLASH_WARN_THRESHOLD_MS)
+}
+
 /// A single flash job.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct FlashJob {
     /// Unique job identifier.
@@ -46,6 +169,12 @@ pub struct FlashJob {
     pub flash_twice: bool,
     /// Display rotation to apply at flash time.
     pub rotation_degrees: u16,
+    /// Dedup key; a later enqueue with a matching key updates this job in
+    /// place instead of queueing a redundant flash.
+    pub unique_key: String,
+    /// Forces a fresh server-side dither even if `dithered_path` already
+    /// exists, for clients that want to pick up changed render settings.
+    pub force_dither: bool,
     /// Job state.
     pub status: FlashJobStatus,
     /// When the job was created (Unix timestamp in milliseconds).
@@ -54,25 +183,92 @@ pub struct FlashJob {
     pub started_at: Option<u64>,
     /// When the job finished (Unix timestamp in milliseconds).
     pub finished_at: Option<u64>,
-    /// Error message (if job failed).
+    /// Error message (if job failed or is retrying after a failure).
     pub error_message: Option<String>,
+    /// Number of attempts made so far (starts at 0, incremented on each failure).
+    pub attempt: u32,
+    /// Maximum number of attempts before the job is given up on permanently.
+    pub max_retries: u32,
+    /// When this job becomes eligible to be dequeued again after a failed
+    /// attempt (Unix timestamp in milliseconds). `None` means eligible now.
+    pub next_attempt_at: Option<u64>,
+    /// Last time the worker confirmed it's still actively flashing this job
+    /// (Unix timestamp in milliseconds). Used to detect a stuck worker.
+    pub last_heartbeat_at: Option<u64>,
+    /// How long the most recent flash attempt took, once it finishes.
+    pub duration_ms: Option<u64>,
+}
+
+/// Parameters for a job that hasn't been assigned an id or initial state yet.
+///
+/// The storage backend is responsible for turning this into a full
+/// [`FlashJob`] (assigning `job_id`, `status`, and `created_at`).
+#[derive(Debug, Clone)]
+pub struct NewFlashJob {
+    pub filename: String,
+    pub dithered_path: String,
+    pub flash_twice: bool,
+    pub rotation_degrees: u16,
+    /// Dedup key. A pending (not yet started) job sharing this key is
+    /// updated in place instead of adding a redundant queue entry.
+    pub unique_key: String,
+    /// Forces a fresh server-side dither even if `dithered_path` already
+    /// exists.
+    pub force_dither: bool,
+}
+
+/// Backing store for the flash queue's job state (current job, pending
+/// queue, and a short history of recently finished jobs for status
+/// polling).
+///
+/// `MemoryStorage` is the original in-process behavior; `PersistentStorage`
+/// additionally survives process restarts, which matters on a Pi that can
+/// be power-cycled mid-queue.
+pub trait FlashStorage: Send + Sync + std::fmt::Debug {
+    /// Enqueues a new job and returns its assigned id.
+    fn push(&mut self, job: NewFlashJob) -> u64;
+    /// Takes the next eligible job from the queue and marks it current, if
+    /// no job is already in flight.
+    fn pop(&mut self) -> Option<FlashJob>;
+    /// Looks up a job by id across current, queued, and recently finished jobs.
+    fn info(&self, job_id: u64) -> Option<FlashJob>;
+    /// Records the final result of a job (normally the current one).
+    /// `duration_ms` is how long the flash attempt took, if known.
+    fn complete(&mut self, job_id: u64, result: Result<(), String>, duration_ms: Option<u64>);
+    /// Moves the current job straight to `Failed`, bypassing the normal
+    /// retry/backoff path. Used for jobs that can never succeed no matter
+    /// how many times they're retried (e.g. their dithered file vanished
+    /// while queued), so they fail fast instead of cycling through
+    /// `max_retries` backoff delays first.
+    fn fail_fast(&mut self, job_id: u64, error: String);
+    /// Gets a clone of the current job (if any).
+    fn current_job(&self) -> Option<FlashJob>;
+    /// Gets a clone of all queued jobs.
+    fn queued_jobs(&self) -> Vec<FlashJob>;
+    /// Gets the queue position for a job id (0 = currently flashing, 1+ = queued).
+    fn position(&self, job_id: u64) -> Option<usize>;
+    /// Clears a finished current job from its slot once its retention window elapses.
+    fn clear_current_if_finished(&mut self);
+    /// Refreshes the current job's heartbeat, if `job_id` matches it.
+    fn heartbeat(&mut self, job_id: u64);
+    /// If the current job is `Flashing` and hasn't heartbeated within
+    /// `stale_timeout_ms`, recovers it (retry or permanent failure, same as
+    /// a normal `complete` error) and returns the recovered job.
+    fn reap_stale_current(&mut self, stale_timeout_ms: u64) -> Option<FlashJob>;
 }
 
-/// The flash queue and current job state.
+/// In-memory job state. Lost on restart; this is the default backend and
+/// preserves the queue's original behavior.
 #[derive(Debug)]
-pub struct FlashQueue {
-    /// Current job being processed (if any).
+pub struct MemoryStorage {
     current_job: Option<FlashJob>,
-    /// Queued jobs waiting to be processed.
     queue: VecDeque<FlashJob>,
-    /// Recently finished jobs retained briefly for status polling.
     recent_jobs: VecDeque<FlashJob>,
-    /// Monotonically increasing job ID counter.
     next_job_id: u64,
 }
 
-impl FlashQueue {
-    /// Creates a new empty flash queue.
+impl MemoryStorage {
+    /// Creates a new empty in-memory store.
     pub fn new() -> Self {
         Self {
             current_job: None,
@@ -82,39 +278,82 @@ impl FlashQueue {
         }
     }
 
-    /// Adds a job to the queue and returns the job ID.
-    pub fn enqueue(
-        &mut self,
-        filename: String,
-        dithered_path: String,
-        flash_twice: bool,
-        rotation_degrees: u16,
-    ) -> u64 {
+    fn prune_recent_jobs(&mut self) {
+        let now = current_time_millis();
+        while let Some(job) = self.recent_jobs.front() {
+            let is_expired = match job.finished_at {
+                Some(finished_at) => {
+                    now > finished_at && (now - finished_at) > FINISHED_JOB_RETENTION_MS
+                }
+                None => true,
+            };
+
+            if !is_expired {
+                break;
+            }
+
+            self.recent_jobs.pop_front();
+        }
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlashStorage for MemoryStorage {
+    fn push(&mut self, job: NewFlashJob) -> u64 {
+        // A pending (not yet started) job with the same key gets its
+        // parameters refreshed in place rather than queueing a redundant
+        // flash of what's effectively the same image.
+        if let Some(existing) = self
+            .queue
+            .iter_mut()
+            .find(|existing| existing.unique_key == job.unique_key)
+        {
+            existing.filename = job.filename;
+            existing.dithered_path = job.dithered_path;
+            existing.flash_twice = job.flash_twice;
+            existing.rotation_degrees = job.rotation_degrees;
+            existing.force_dither = job.force_dither;
+            return existing.job_id;
+        }
+
         let job_id = self.next_job_id;
         self.next_job_id += 1;
 
         let job = FlashJob {
             job_id,
-            filename,
-            dithered_path,
-            flash_twice,
-            rotation_degrees,
+            filename: job.filename,
+            dithered_path: job.dithered_path,
+            flash_twice: job.flash_twice,
+            rotation_degrees: job.rotation_degrees,
+            unique_key: job.unique_key,
+            force_dither: job.force_dither,
             status: FlashJobStatus::Queued,
             created_at: current_time_millis(),
             started_at: None,
             finished_at: None,
             error_message: None,
+            attempt: 0,
+            max_retries: get_max_flash_retries(),
+            next_attempt_at: None,
+            last_heartbeat_at: None,
+            duration_ms: None,
         };
 
         self.queue.push_back(job);
         job_id
     }
 
-    /// Takes the next job from the queue (if any) and marks it as current.
-    fn dequeue(&mut self) -> Option<FlashJob> {
+    fn pop(&mut self) -> Option<FlashJob> {
         if let Some(ref current) = self.current_job {
             match current.status {
-                FlashJobStatus::Flashing | FlashJobStatus::Queued => return None,
+                FlashJobStatus::Flashing | FlashJobStatus::Queued | FlashJobStatus::Retrying => {
+                    return None
+                }
                 FlashJobStatus::Completed | FlashJobStatus::Failed => {
                     self.recent_jobs.push_back(current.clone());
                     self.current_job = None;
@@ -122,32 +361,113 @@ impl FlashQueue {
             }
         }
 
-        self.queue.pop_front().map(|mut job| {
+        let now = current_time_millis();
+        let idx = self.queue.iter().position(|job| match job.next_attempt_at {
+            Some(next_attempt_at) => next_attempt_at <= now,
+            None => true,
+        })?;
+
+        self.queue.remove(idx).map(|mut job| {
             job.status = FlashJobStatus::Flashing;
-            job.started_at = Some(current_time_millis());
+            job.started_at = Some(now);
+            job.next_attempt_at = None;
+            job.last_heartbeat_at = Some(now);
             self.current_job = Some(job.clone());
             job
         })
     }
 
-    /// Marks the current job as completed.
-    fn mark_completed(&mut self) {
-        if let Some(ref mut job) = self.current_job {
-            job.status = FlashJobStatus::Completed;
-            job.finished_at = Some(current_time_millis());
+    fn info(&self, job_id: u64) -> Option<FlashJob> {
+        if let Some(ref current) = self.current_job {
+            if current.job_id == job_id {
+                return Some(current.clone());
+            }
+        }
+
+        if let Some(job) = self.queue.iter().find(|job| job.job_id == job_id) {
+            return Some(job.clone());
         }
+
+        self.recent_jobs
+            .iter()
+            .find(|job| job.job_id == job_id)
+            .cloned()
     }
 
-    /// Marks the current job as failed.
-    fn mark_failed(&mut self, error: String) {
-        if let Some(ref mut job) = self.current_job {
-            job.status = FlashJobStatus::Failed;
-            job.finished_at = Some(current_time_millis());
-            job.error_message = Some(error);
+    fn complete(&mut self, job_id: u64, result: Result<(), String>, duration_ms: Option<u64>) {
+        let Some(current) = self.current_job.as_ref() else {
+            return;
+        };
+        if current.job_id != job_id {
+            return;
+        }
+        let mut job = current.clone();
+        let now = current_time_millis();
+        if duration_ms.is_some() {
+            job.duration_ms = duration_ms;
+        }
+
+        match result {
+            Ok(()) => {
+                job.status = FlashJobStatus::Completed;
+                job.finished_at = Some(now);
+                self.current_job = Some(job);
+            }
+            Err(error) => {
+                job.attempt += 1;
+                if job.attempt < job.max_retries {
+                    job.status = FlashJobStatus::Retrying;
+                    job.next_attempt_at = Some(now + backoff_delay_ms(job.attempt));
+                    job.error_message = Some(error);
+                    // Free the current slot immediately so other ready jobs
+                    // aren't blocked behind this one's backoff delay.
+                    self.current_job = None;
+                    self.queue.push_back(job);
+                } else {
+                    job.status = FlashJobStatus::Failed;
+                    job.finished_at = Some(now);
+                    job.error_message = Some(error);
+                    self.current_job = Some(job);
+                }
+            }
         }
     }
 
-    /// Clears completed/failed job from current_job slot after delay.
+    fn fail_fast(&mut self, job_id: u64, error: String) {
+        let Some(current) = self.current_job.as_ref() else {
+            return;
+        };
+        if current.job_id != job_id {
+            return;
+        }
+        let mut job = current.clone();
+        job.status = FlashJobStatus::Failed;
+        job.finished_at = Some(current_time_millis());
+        job.error_message = Some(error);
+        self.current_job = Some(job);
+    }
+
+    fn current_job(&self) -> Option<FlashJob> {
+        self.current_job.clone()
+    }
+
+    fn queued_jobs(&self) -> Vec<FlashJob> {
+        self.queue.iter().cloned().collect()
+    }
+
+    fn position(&self, job_id: u64) -> Option<usize> {
+        if let Some(ref current) = self.current_job {
+            if current.job_id == job_id {
+                return Some(0);
+            }
+        }
+
+        self.queue
+            .iter()
+            .position(|job| job.job_id == job_id)
+            .map(|pos| pos + 1)
+    }
+
     fn clear_current_if_finished(&mut self) {
         self.prune_recent_jobs();
 
@@ -166,64 +486,403 @@ impl FlashQueue {
         }
     }
 
-    fn prune_recent_jobs(&mut self) {
+    fn heartbeat(&mut self, job_id: u64) {
+        if let Some(job) = self.current_job.as_mut() {
+            if job.job_id == job_id {
+                job.last_heartbeat_at = Some(current_time_millis());
+            }
+        }
+    }
+
+    fn reap_stale_current(&mut self, stale_timeout_ms: u64) -> Option<FlashJob> {
+        let current = self.current_job.as_ref()?;
+        if current.status != FlashJobStatus::Flashing {
+            return None;
+        }
+
         let now = current_time_millis();
-        while let Some(job) = self.recent_jobs.front() {
-            let is_expired = match job.finished_at {
-                Some(finished_at) => {
-                    now > finished_at && (now - finished_at) > FINISHED_JOB_RETENTION_MS
-                }
-                None => true,
-            };
+        let last_seen = current
+            .last_heartbeat_at
+            .or(current.started_at)
+            .unwrap_or(current.created_at);
+        if now.saturating_sub(last_seen) <= stale_timeout_ms {
+            return None;
+        }
 
-            if !is_expired {
-                break;
+        let job_id = current.job_id;
+        self.complete(job_id, Err("worker timed out".to_string()), None);
+        self.info(job_id)
+    }
+}
+
+/// On-disk snapshot of a [`MemoryStorage`], used by [`PersistentStorage`].
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct FlashQueueSnapshot {
+    current_job: Option<FlashJob>,
+    queue: VecDeque<FlashJob>,
+    recent_jobs: VecDeque<FlashJob>,
+    next_job_id: u64,
+}
+
+/// JSON-file-backed storage. Wraps a [`MemoryStorage`] and persists a
+/// snapshot after every mutation, so the queue (including `next_job_id`,
+/// to avoid id collisions after restart) survives process restarts.
+#[derive(Debug)]
+pub struct PersistentStorage {
+    memory: MemoryStorage,
+    path: PathBuf,
+}
+
+impl PersistentStorage {
+    /// Opens (or creates) a persistent store at `path`, rehydrating any
+    /// previously saved queue state.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let memory = Self::load(&path).unwrap_or_default();
+        Self { memory, path }
+    }
+
+    fn load(path: &Path) -> Option<MemoryStorage> {
+        let contents = fs::read_to_string(path).ok()?;
+        let snapshot: FlashQueueSnapshot = match serde_json::from_str(&contents) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                error!(
+                    "Failed to parse persisted flash queue at '{}': {}",
+                    path.display(),
+                    e
+                );
+                return None;
             }
+        };
 
-            self.recent_jobs.pop_front();
+        Some(MemoryStorage {
+            current_job: snapshot.current_job,
+            queue: snapshot.queue,
+            recent_jobs: snapshot.recent_jobs,
+            next_job_id: snapshot.next_job_id,
+        })
+    }
+
+    /// Writes the current state to disk via a temp-file-then-rename so a
+    /// crash mid-write can't leave a truncated queue file behind.
+    fn persist(&self) {
+        let snapshot = FlashQueueSnapshot {
+            current_job: self.memory.current_job.clone(),
+            queue: self.memory.queue.clone(),
+            recent_jobs: self.memory.recent_jobs.clone(),
+            next_job_id: self.memory.next_job_id,
+        };
+
+        let json = match serde_json::to_string_pretty(&snapshot) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize flash queue: {}", e);
+                return;
+            }
+        };
+
+        if let Some(parent) = self.path.parent() {
+            if let Err(e) = fs::create_dir_all(parent) {
+                error!("Failed to create flash queue directory: {}", e);
+                return;
+            }
+        }
+
+        let temp_path = self.path.with_extension("json.tmp");
+        if let Err(e) = fs::write(&temp_path, json) {
+            error!("Failed to write flash queue to '{}': {}", temp_path.display(), e);
+            return;
+        }
+
+        if let Err(e) = fs::rename(&temp_path, &self.path) {
+            error!(
+                "Failed to persist flash queue to '{}': {}",
+                self.path.display(),
+                e
+            );
+            let _ = fs::remove_file(&temp_path);
         }
     }
+}
 
-    /// Gets the queue position for a job ID (0 = currently flashing, 1+ = queued).
-    pub fn get_position(&self, job_id: u64) -> Option<usize> {
-        if let Some(ref current) = self.current_job {
-            if current.job_id == job_id {
-                return Some(0);
+impl FlashStorage for PersistentStorage {
+    fn push(&mut self, job: NewFlashJob) -> u64 {
+        let job_id = self.memory.push(job);
+        self.persist();
+        job_id
+    }
+
+    fn pop(&mut self) -> Option<FlashJob> {
+        let job = self.memory.pop();
+        self.persist();
+        job
+    }
+
+    fn info(&self, job_id: u64) -> Option<FlashJob> {
+        self.memory.info(job_id)
+    }
+
+    fn complete(&mut self, job_id: u64, result: Result<(), String>, duration_ms: Option<u64>) {
+        self.memory.complete(job_id, result, duration_ms);
+        self.persist();
+    }
+
+    fn fail_fast(&mut self, job_id: u64, error: String) {
+        self.memory.fail_fast(job_id, error);
+        self.persist();
+    }
+
+    fn current_job(&self) -> Option<FlashJob> {
+        self.memory.current_job()
+    }
+
+    fn queued_jobs(&self) -> Vec<FlashJob> {
+        self.memory.queued_jobs()
+    }
+
+    fn position(&self, job_id: u64) -> Option<usize> {
+        self.memory.position(job_id)
+    }
+
+    fn clear_current_if_finished(&mut self) {
+        self.memory.clear_current_if_finished();
+        self.persist();
+    }
+
+    fn heartbeat(&mut self, job_id: u64) {
+        // Heartbeats are frequent and purely diagnostic; skip the disk
+        // round-trip and let the next real mutation persist the latest one.
+        self.memory.heartbeat(job_id);
+    }
+
+    fn reap_stale_current(&mut self, stale_timeout_ms: u64) -> Option<FlashJob> {
+        let recovered = self.memory.reap_stale_current(stale_timeout_ms);
+        if recovered.is_some() {
+            self.persist();
+        }
+        recovered
+    }
+}
+
+/// The flash queue, delegating all job state to a [`FlashStorage`] backend.
+#[derive(Debug)]
+pub struct FlashQueue {
+    storage: Box<dyn FlashStorage>,
+    /// Set by `request_shutdown`; once true the worker stops dequeuing new
+    /// jobs but finishes any job already in flight.
+    shutdown_requested: bool,
+    /// Publishes job state transitions for `/api/flash/events` subscribers.
+    /// `send` is a no-op (besides being dropped) when nobody's listening, so
+    /// every queue mutation can publish unconditionally.
+    events: broadcast::Sender<FlashEvent>,
+}
+
+impl FlashQueue {
+    /// Creates a new flash queue backed by in-memory storage only.
+    pub fn new() -> Self {
+        Self::with_storage(Box::new(MemoryStorage::new()))
+    }
+
+    /// Creates a flash queue backed by a custom [`FlashStorage`] implementation.
+    pub fn with_storage(storage: Box<dyn FlashStorage>) -> Self {
+        let (events, _) = broadcast::channel(FLASH_EVENTS_CHANNEL_CAPACITY);
+        Self {
+            storage,
+            shutdown_requested: false,
+            events,
+        }
+    }
+
+    /// Creates a flash queue backed by a JSON file at `path`, rehydrating
+    /// any jobs left over from a previous run so `spawn_flash_worker` picks
+    /// up where it left off.
+    pub fn persistent(path: impl Into<PathBuf>) -> Self {
+        Self::with_storage(Box::new(PersistentStorage::new(path)))
+    }
+
+    /// Adds a job to the queue and returns the job ID.
+    ///
+    /// `unique_key` defaults to `dithered_path` when `None`. If a pending
+    /// (not yet started) job with the same key is already queued, it's
+    /// updated in place and its job id is returned instead of queueing a
+    /// redundant flash of what's effectively the same image.
+    pub fn enqueue(
+        &mut self,
+        filename: String,
+        dithered_path: String,
+        flash_twice: bool,
+        rotation_degrees: u16,
+        unique_key: Option<String>,
+        force_dither: bool,
+    ) -> u64 {
+        let unique_key = unique_key.unwrap_or_else(|| dithered_path.clone());
+        let job_id = self.storage.push(NewFlashJob {
+            filename,
+            dithered_path,
+            flash_twice,
+            rotation_degrees,
+            unique_key,
+            force_dither,
+        });
+        if let Some(job) = self.storage.info(job_id) {
+            let _ = self.events.send(FlashEvent::Enqueued { job });
+        }
+        job_id
+    }
+
+    /// Takes the next job from the queue (if any) and marks it as current.
+    fn dequeue(&mut self) -> Option<FlashJob> {
+        let job = self.storage.pop()?;
+        let _ = self.events.send(FlashEvent::Started { job: job.clone() });
+        Some(job)
+    }
+
+    /// Marks the current job as completed, recording how long it took.
+    fn mark_completed(&mut self, duration_ms: u64) {
+        if let Some(current) = self.storage.current_job() {
+            let job_id = current.job_id;
+            self.storage.complete(job_id, Ok(()), Some(duration_ms));
+            if let Some(job) = self.storage.info(job_id) {
+                let _ = self.events.send(FlashEvent::Completed { job });
             }
         }
+    }
 
-        self.queue
-            .iter()
-            .position(|job| job.job_id == job_id)
-            .map(|pos| pos + 1)
+    /// Marks the current job as failed, recording how long the attempt took.
+    fn mark_failed(&mut self, error: String, duration_ms: u64) {
+        if let Some(current) = self.storage.current_job() {
+            let job_id = current.job_id;
+            self.storage.complete(job_id, Err(error), Some(duration_ms));
+            if let Some(job) = self.storage.info(job_id) {
+                let event = match job.status {
+                    FlashJobStatus::Failed => FlashEvent::Failed { job },
+                    _ => FlashEvent::Retrying { job },
+                };
+                let _ = self.events.send(event);
+            }
+        }
+    }
+
+    /// Fails the current job immediately, skipping the retry/backoff path
+    /// entirely. For jobs that can never succeed (e.g. their dithered file
+    /// vanished while queued) rather than waste `max_retries` attempts on
+    /// a guaranteed-bad job.
+    fn fail_fast(&mut self, error: String) {
+        if let Some(current) = self.storage.current_job() {
+            let job_id = current.job_id;
+            self.storage.fail_fast(job_id, error);
+            if let Some(job) = self.storage.info(job_id) {
+                let _ = self.events.send(FlashEvent::Failed { job });
+            }
+        }
+    }
+
+    /// Publishes a phase transition for the job currently being flashed, so
+    /// SSE subscribers see "dithering" / "flashing" / "second-flash" without
+    /// needing a full job-state change. A no-op if nobody's subscribed.
+    pub fn publish_phase(&self, job_id: u64, phase: impl Into<String>) {
+        let _ = self.events.send(FlashEvent::Phase {
+            job_id,
+            phase: phase.into(),
+        });
+    }
+
+    /// Clones the event sender so a long-running task (e.g. the flash
+    /// worker's in-progress flash) can publish phase events without holding
+    /// the queue's mutex for the duration of the flash.
+    pub fn events_sender(&self) -> broadcast::Sender<FlashEvent> {
+        self.events.clone()
+    }
+
+    /// Subscribes to future job events. Pair with `events_snapshot` (or
+    /// `events_snapshot_for`) taken under the same lock so a client
+    /// connecting mid-queue doesn't miss the gap between the snapshot and
+    /// its first live event.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<FlashEvent> {
+        self.events.subscribe()
+    }
+
+    /// A synthetic event per in-flight job (current, then queued, in
+    /// position order), reflecting the queue's state right now. Sent to a
+    /// new SSE subscriber before forwarding live events.
+    pub fn events_snapshot(&self) -> Vec<FlashEvent> {
+        let mut events = Vec::new();
+        if let Some(job) = self.storage.current_job() {
+            events.push(snapshot_event(job));
+        }
+        events.extend(self.storage.queued_jobs().into_iter().map(snapshot_event));
+        events
+    }
+
+    /// Like `events_snapshot`, but for a single job id (for
+    /// `/api/flash/events/<job_id>`). `None` if the job isn't known (never
+    /// existed, or aged out of recently-finished retention).
+    pub fn events_snapshot_for(&self, job_id: u64) -> Option<FlashEvent> {
+        self.storage.info(job_id).map(snapshot_event)
+    }
+
+    /// Clears completed/failed job from current_job slot after delay.
+    fn clear_current_if_finished(&mut self) {
+        self.storage.clear_current_if_finished();
+    }
+
+    /// Refreshes the current job's heartbeat, confirming the worker is
+    /// still actively flashing it.
+    fn heartbeat(&mut self, job_id: u64) {
+        self.storage.heartbeat(job_id);
+    }
+
+    /// Recovers a `Flashing` job whose heartbeat has gone stale (worker
+    /// crashed, subprocess wedged, or a server restart found one mid-flash),
+    /// requeuing it for retry or failing it permanently per the usual retry
+    /// limit.
+    fn reap_stale_current(&mut self) -> Option<FlashJob> {
+        let recovered = self.storage.reap_stale_current(get_flash_stale_timeout_ms())?;
+        let event = match recovered.status {
+            FlashJobStatus::Failed => FlashEvent::Failed { job: recovered.clone() },
+            _ => FlashEvent::Retrying { job: recovered.clone() },
+        };
+        let _ = self.events.send(event);
+        Some(recovered)
+    }
+
+    /// Requests that the worker drain its in-progress flash (if any) and
+    /// exit instead of dequeuing further jobs. Safe to call more than once.
+    pub fn request_shutdown(&mut self) {
+        self.shutdown_requested = true;
+    }
+
+    /// Whether `request_shutdown` has been called.
+    pub fn shutdown_requested(&self) -> bool {
+        self.shutdown_requested
+    }
+
+    /// Number of jobs left unprocessed (current plus queued), for reporting
+    /// how much work a shutdown left behind.
+    pub fn pending_job_count(&self) -> usize {
+        self.storage.current_job().is_some() as usize + self.storage.queued_jobs().len()
+    }
+
+    /// Gets the queue position for a job ID (0 = currently flashing, 1+ = queued).
+    pub fn get_position(&self, job_id: u64) -> Option<usize> {
+        self.storage.position(job_id)
     }
 
     /// Gets a clone of the current job (if any).
     pub fn get_current_job(&self) -> Option<FlashJob> {
-        self.current_job.clone()
+        self.storage.current_job()
     }
 
     /// Gets a clone of all queued jobs.
     pub fn get_queued_jobs(&self) -> Vec<FlashJob> {
-        self.queue.iter().cloned().collect()
+        self.storage.queued_jobs()
     }
 
     /// Finds a job by ID across current, queued, and recently finished jobs.
     pub fn find_job(&self, job_id: u64) -> Option<FlashJob> {
-        if let Some(ref current) = self.current_job {
-            if current.job_id == job_id {
-                return Some(current.clone());
-            }
-        }
-
-        if let Some(job) = self.queue.iter().find(|job| job.job_id == job_id) {
-            return Some(job.clone());
-        }
-
-        self.recent_jobs
-            .iter()
-            .find(|job| job.job_id == job_id)
-            .cloned()
+        self.storage.info(job_id)
     }
 }
 
@@ -241,101 +900,215 @@ fn current_time_millis() -> u64 {
         .unwrap_or(0)
 }
 
+/// Maps a job's current status to the event a late subscriber would have
+/// seen, had it been watching since that transition happened.
+fn snapshot_event(job: FlashJob) -> FlashEvent {
+    match job.status {
+        FlashJobStatus::Queued => FlashEvent::Enqueued { job },
+        FlashJobStatus::Flashing => FlashEvent::Started { job },
+        FlashJobStatus::Retrying => FlashEvent::Retrying { job },
+        FlashJobStatus::Completed => FlashEvent::Completed { job },
+        FlashJobStatus::Failed => FlashEvent::Failed { job },
+    }
+}
+
 /// Spawns the background flash worker task.
-pub fn spawn_flash_worker(queue_state: FlashQueueState) {
+pub fn spawn_flash_worker(queue_state: FlashQueueState, stores: ImageStores) -> tokio::task::JoinHandle<()> {
     tokio::spawn(async move {
         info!("Flash queue worker started");
 
         loop {
-            // Check for next job.
+            // Check for next job. A job found here in `Flashing` state means
+            // the previous worker run died mid-flash (persistence rehydrated
+            // it); reap_stale_current recovers it the same as any other
+            // stuck job once its heartbeat is judged too old.
             let job = {
                 let mut queue = queue_state.lock().await;
+                if let Some(recovered) = queue.reap_stale_current() {
+                    warn!(
+                        "Recovered stale flash job {} (no heartbeat, now {:?})",
+                        recovered.job_id, recovered.status
+                    );
+                }
                 queue.clear_current_if_finished();
-                queue.dequeue()
+
+                // Stop picking up new work once shutdown has been
+                // requested; any job already in flight still runs to
+                // completion below.
+                if queue.shutdown_requested() {
+                    None
+                } else {
+                    queue.dequeue()
+                }
             };
 
             if let Some(job) = job {
+                let events = queue_state.lock().await.events_sender();
+
+                // Render a dithered image server-side if one isn't already
+                // waiting (the client skipped pre-dithering, the dithered
+                // file vanished while queued, or `force_dither` was set).
+                // A render failure means this job can never succeed no
+                // matter how many times it's retried, so fail it
+                // immediately instead of burning through max_retries
+                // backoff delays first.
+                let _ = events.send(FlashEvent::Phase {
+                    job_id: job.job_id,
+                    phase: "dithering".to_string(),
+                });
+                if let Err(e) = dither::ensure_dithered(&job.filename, &job.dithered_path, job.force_dither, &stores).await {
+                    warn!("Flash job {} failing fast: {}", job.job_id, e);
+                    queue_state.lock().await.fail_fast(e);
+                    continue;
+                }
+
                 info!(
                     "Processing flash job {}: {} (flash_twice: {}, rotation: {})",
                     job.job_id, job.filename, job.flash_twice, job.rotation_degrees
                 );
 
-                // Execute flash operation.
-                let result =
-                    execute_flash(&job.dithered_path, job.flash_twice, job.rotation_degrees).await;
+                // Execute the flash while periodically refreshing its
+                // heartbeat (so a hung subprocess can be detected and
+                // recovered instead of wedging the queue forever) and
+                // watching for it running suspiciously long.
+                let started_at = std::time::Instant::now();
+                let warn_threshold = Duration::from_millis(get_flash_warn_threshold_ms());
+                let mut warned_slow = false;
+
+                let job_id = job.job_id;
+                let phase_events = events.clone();
+                let flash_future = execute_flash(&job.dithered_path, job.flash_twice, job.rotation_degrees, move |phase| {
+                    let _ = phase_events.send(FlashEvent::Phase {
+                        job_id,
+                        phase: phase.to_string(),
+                    });
+                });
+                tokio::pin!(flash_future);
+                let mut heartbeat_ticker =
+                    time::interval(Duration::from_millis(get_flash_heartbeat_interval_ms()));
+
+                let result = loop {
+                    tokio::select! {
+                        result = &mut flash_future => break result,
+                        _ = heartbeat_ticker.tick() => {
+                            queue_state.lock().await.heartbeat(job.job_id);
+                            if !warned_slow && started_at.elapsed() > warn_threshold {
+                                warned_slow = true;
+                                warn!(
+                                    "Flash job {} has been running for {:?}, longer than the {:?} warn threshold",
+                                    job.job_id, started_at.elapsed(), warn_threshold
+                                );
+                            }
+                        }
+                    }
+                };
+
+                let duration_ms = started_at.elapsed().as_millis() as u64;
 
                 // Update queue state.
                 let mut queue = queue_state.lock().await;
                 match result {
                     Ok(()) => {
-                        info!("Flash job {} completed successfully", job.job_id);
-                        queue.mark_completed();
+                        info!(
+                            "Flash job {} completed successfully in {}ms",
+                            job.job_id, duration_ms
+                        );
+                        queue.mark_completed(duration_ms);
                     }
                     Err(e) => {
-                        error!("Flash job {} failed: {}", job.job_id, e);
-                        queue.mark_failed(e);
+                        error!("Flash job {} failed after {}ms: {}", job.job_id, duration_ms, e);
+                        queue.mark_failed(e, duration_ms);
                     }
                 }
             } else {
+                let queue = queue_state.lock().await;
+                if queue.shutdown_requested() {
+                    let remaining = queue.pending_job_count();
+                    info!(
+                        "Flash queue worker shutting down ({} job(s) left unprocessed)",
+                        remaining
+                    );
+                    break;
+                }
+                drop(queue);
+
                 // No jobs, sleep briefly before checking again.
                 time::sleep(Duration::from_millis(500)).await;
             }
         }
-    });
+    })
 }
 
 /// Executes the actual flash operation by running the Python script.
+/// `on_phase` is called synchronously right before each flash attempt so
+/// the caller can publish a phase transition without awaiting anything.
 async fn execute_flash(
     dithered_path: &str,
     flash_twice: bool,
     rotation_degrees: u16,
+    on_phase: impl Fn(&str),
 ) -> Result<(), String> {
     debug!("Executing flash script for {}", dithered_path);
 
+    on_phase("flashing");
+    run_update_display(dithered_path, rotation_degrees, "Flash").await?;
+
+    // Maybe flash again.
+    if flash_twice {
+        debug!("Executing second flash for {}", dithered_path);
+        on_phase("second-flash");
+        run_update_display(dithered_path, rotation_degrees, "Second flash").await?;
+    }
+
+    Ok(())
+}
+
+/// Runs a single `inky-soup-update-display` invocation, killing and failing
+/// it if it doesn't finish within `get_flash_timeout_ms()`. `label` is used
+/// only to distinguish the first flash from the repeat one in error messages.
+async fn run_update_display(
+    dithered_path: &str,
+    rotation_degrees: u16,
+    label: &str,
+) -> Result<(), String> {
     // TODO: Port e2e tests to Docker environment mimicking production.
-    let output = Command::new("/usr/bin/inky-soup-update-display")
+    let child = Command::new("/usr/bin/inky-soup-update-display")
         .arg(dithered_path)
         .arg("--skip-dither")
         .arg("--rotation")
         .arg(rotation_degrees.to_string())
-        .output()
-        .await
-        .map_err(|e| format!("Failed to execute script: {}", e))?;
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to execute {}: {}", label, e))?;
+
+    let timeout_duration = Duration::from_millis(get_flash_timeout_ms());
+    let output = match time::timeout(timeout_duration, child.wait_with_output()).await {
+        Ok(result) => result.map_err(|e| format!("Failed to execute {}: {}", label, e))?,
+        Err(_) => {
+            // `kill_on_drop` kills the child as the timed-out future (and
+            // the `Child` it owns) is dropped here.
+            return Err(format!(
+                "{} timed out after {}ms",
+                label,
+                timeout_duration.as_millis()
+            ));
+        }
+    };
 
     if !output.status.success() {
         let exit_code = output.status.code().unwrap_or(-1);
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(format!(
-            "Flash failed (exit code {}): {}",
+            "{} failed (exit code {}): {}",
+            label,
             exit_code,
             stderr.trim()
         ));
     }
 
-    // Maybe flash again.
-    if flash_twice {
-        debug!("Executing second flash for {}", dithered_path);
-
-        let output2 = Command::new("/usr/bin/inky-soup-update-display")
-            .arg(dithered_path)
-            .arg("--skip-dither")
-            .arg("--rotation")
-            .arg(rotation_degrees.to_string())
-            .output()
-            .await
-            .map_err(|e| format!("Failed to execute second flash: {}", e))?;
-
-        if !output2.status.success() {
-            let exit_code = output2.status.code().unwrap_or(-1);
-            let stderr = String::from_utf8_lossy(&output2.stderr);
-            return Err(format!(
-                "Second flash failed (exit code {}): {}",
-                exit_code,
-                stderr.trim()
-            ));
-        }
-    }
-
     Ok(())
 }
 
@@ -343,11 +1116,27 @@ async fn execute_flash(
 mod tests {
     use super::*;
 
+    impl FlashQueue {
+        /// Test helper: forcibly clears the current job slot, bypassing the
+        /// normal finished-job retention window so FIFO ordering can be
+        /// tested without waiting out `FINISHED_JOB_RETENTION_MS`.
+        fn force_clear_current(&mut self) {
+            let queue = self.storage.queued_jobs();
+            let next_job_id = queue.iter().map(|j| j.job_id + 1).max().unwrap_or(1);
+            self.storage = Box::new(MemoryStorage {
+                current_job: None,
+                queue: queue.into(),
+                recent_jobs: VecDeque::new(),
+                next_job_id,
+            });
+        }
+    }
+
     #[test]
     fn test_enqueue_increments_job_id() {
         let mut queue = FlashQueue::new();
-        let id1 = queue.enqueue("a.jpg".into(), "path/a.jpg.png".into(), false, 0);
-        let id2 = queue.enqueue("b.jpg".into(), "path/b.jpg.png".into(), false, 90);
+        let id1 = queue.enqueue("a.jpg".into(), "path/a.jpg.png".into(), false, 0, None, false);
+        let id2 = queue.enqueue("b.jpg".into(), "path/b.jpg.png".into(), false, 90, None, false);
         assert_eq!(id1, 1);
         assert_eq!(id2, 2);
     }
@@ -355,9 +1144,9 @@ mod tests {
     #[test]
     fn test_get_position() {
         let mut queue = FlashQueue::new();
-        let id1 = queue.enqueue("a.jpg".into(), "path/a.jpg.png".into(), false, 0);
-        let id2 = queue.enqueue("b.jpg".into(), "path/b.jpg.png".into(), false, 0);
-        let id3 = queue.enqueue("c.jpg".into(), "path/c.jpg.png".into(), false, 0);
+        let id1 = queue.enqueue("a.jpg".into(), "path/a.jpg.png".into(), false, 0, None, false);
+        let id2 = queue.enqueue("b.jpg".into(), "path/b.jpg.png".into(), false, 0, None, false);
+        let id3 = queue.enqueue("c.jpg".into(), "path/c.jpg.png".into(), false, 0, None, false);
 
         // All in queue, positions are 1, 2, 3.
         assert_eq!(queue.get_position(id1), Some(1));
@@ -374,15 +1163,15 @@ mod tests {
     #[test]
     fn test_dequeue_fifo_order() {
         let mut queue = FlashQueue::new();
-        queue.enqueue("first.jpg".into(), "path/first.jpg.png".into(), false, 0);
-        queue.enqueue("second.jpg".into(), "path/second.jpg.png".into(), true, 270);
+        queue.enqueue("first.jpg".into(), "path/first.jpg.png".into(), false, 0, None, false);
+        queue.enqueue("second.jpg".into(), "path/second.jpg.png".into(), true, 270, None, false);
 
         let job1 = queue.dequeue().unwrap();
         assert_eq!(job1.filename, "first.jpg");
         assert!(!job1.flash_twice);
 
-        // First job is now current, clear it.
-        queue.current_job = None;
+        // First job is now current; clear it without waiting out the retention window.
+        queue.force_clear_current();
 
         let job2 = queue.dequeue().unwrap();
         assert_eq!(job2.filename, "second.jpg");
@@ -393,20 +1182,65 @@ mod tests {
     #[test]
     fn test_job_status_transitions() {
         let mut queue = FlashQueue::new();
-        queue.enqueue("test.jpg".into(), "path/test.jpg.png".into(), false, 180);
+        queue.enqueue("test.jpg".into(), "path/test.jpg.png".into(), false, 180, None, false);
 
         // Job starts as Queued.
-        let queued_job = queue.queue.front().unwrap();
-        assert_eq!(queued_job.status, FlashJobStatus::Queued);
+        let queued_job = queue.get_queued_jobs();
+        assert_eq!(queued_job[0].status, FlashJobStatus::Queued);
 
         // Dequeue marks as Flashing.
         let job = queue.dequeue().unwrap();
         assert_eq!(job.status, FlashJobStatus::Flashing);
 
         // Mark completed.
-        queue.mark_completed();
+        queue.mark_completed(1234);
         let current = queue.get_current_job().unwrap();
         assert_eq!(current.status, FlashJobStatus::Completed);
+        assert_eq!(current.duration_ms, Some(1234));
+    }
+
+    #[test]
+    fn test_failed_job_is_requeued_for_retry() {
+        let mut queue = FlashQueue::new();
+        let job_id = queue.enqueue("flaky.jpg".into(), "path/flaky.jpg.png".into(), false, 0, None, false);
+
+        queue.dequeue();
+        queue.mark_failed("transient SPI error".into(), 50);
+
+        // Under the default retry limit, the job goes back to Retrying
+        // rather than Failed, and the current slot is freed immediately.
+        assert!(queue.get_current_job().is_none());
+        let retrying = queue
+            .find_job(job_id)
+            .expect("retrying job should still be tracked");
+        assert_eq!(retrying.status, FlashJobStatus::Retrying);
+        assert_eq!(retrying.attempt, 1);
+        assert!(retrying.next_attempt_at.is_some());
+
+        // Its backoff delay hasn't elapsed, so it's not eligible yet.
+        assert!(queue.dequeue().is_none());
+    }
+
+    #[test]
+    fn test_stale_flashing_job_is_recovered() {
+        let mut queue = FlashQueue::new();
+        let job_id = queue.enqueue("stuck.jpg".into(), "path/stuck.jpg.png".into(), false, 0, None, false);
+        queue.dequeue();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        // With a zero stale-timeout, any heartbeat older than 0 ms is stale,
+        // so a short sleep stands in for a real heartbeat interval.
+        let recovered = queue
+            .storage
+            .reap_stale_current(0)
+            .expect("stuck job should be recovered");
+        assert_eq!(recovered.job_id, job_id);
+        assert_eq!(recovered.status, FlashJobStatus::Retrying);
+        assert_eq!(recovered.error_message.as_deref(), Some("worker timed out"));
+
+        // The slot is free again; the job waits in the queue for its
+        // backoff to elapse before it can be dequeued again.
+        assert!(queue.get_current_job().is_none());
     }
 
     #[test]
@@ -415,15 +1249,66 @@ mod tests {
         assert_eq!(queue.get_position(999), None);
     }
 
+    #[test]
+    fn test_duplicate_unique_key_coalesces_pending_job() {
+        let mut queue = FlashQueue::new();
+        let id1 = queue.enqueue(
+            "photo.jpg".into(),
+            "path/photo.jpg.png".into(),
+            false,
+            0,
+            None,
+            false,
+        );
+        // Re-uploading/refreshing the same image before it's flashed should
+        // update the pending job in place, not queue a second one.
+        let id2 = queue.enqueue(
+            "photo.jpg".into(),
+            "path/photo.jpg.png".into(),
+            true,
+            90,
+            None,
+            false,
+        );
+        assert_eq!(id1, id2);
+        assert_eq!(queue.get_queued_jobs().len(), 1);
+
+        let job = queue.find_job(id1).unwrap();
+        assert!(job.flash_twice);
+        assert_eq!(job.rotation_degrees, 90);
+    }
+
+    #[test]
+    fn test_distinct_unique_key_does_not_coalesce() {
+        let mut queue = FlashQueue::new();
+        queue.enqueue(
+            "a.jpg".into(),
+            "path/shared.png".into(),
+            false,
+            0,
+            Some("a".into()),
+            false,
+        );
+        queue.enqueue(
+            "b.jpg".into(),
+            "path/shared.png".into(),
+            false,
+            0,
+            Some("b".into()),
+            false,
+        );
+        assert_eq!(queue.get_queued_jobs().len(), 2);
+    }
+
     #[test]
     fn test_finished_job_retained_while_next_job_flashing() {
         let mut queue = FlashQueue::new();
-        let first_id = queue.enqueue("first.jpg".into(), "path/first.jpg.png".into(), false, 0);
-        let second_id = queue.enqueue("second.jpg".into(), "path/second.jpg.png".into(), false, 90);
+        let first_id = queue.enqueue("first.jpg".into(), "path/first.jpg.png".into(), false, 0, None, false);
+        let second_id = queue.enqueue("second.jpg".into(), "path/second.jpg.png".into(), false, 90, None, false);
 
         // Start and complete first job.
         queue.dequeue();
-        queue.mark_completed();
+        queue.mark_completed(100);
 
         // Start second job; first should move to retained jobs.
         let second = queue.dequeue().unwrap();
@@ -435,4 +1320,29 @@ mod tests {
             .expect("first job should still be retained");
         assert_eq!(first.status, FlashJobStatus::Completed);
     }
+
+    #[test]
+    fn test_persistent_storage_survives_restart() {
+        let dir = std::env::temp_dir().join(format!(
+            "inky-soup-flash-queue-test-{}",
+            current_time_millis()
+        ));
+        let path = dir.join("flash-queue.json");
+
+        {
+            let mut queue = FlashQueue::persistent(&path);
+            queue.enqueue("a.jpg".into(), "path/a.jpg.png".into(), false, 0, None, false);
+            queue.enqueue("b.jpg".into(), "path/b.jpg.png".into(), false, 0, None, false);
+        }
+
+        let mut restarted = FlashQueue::persistent(&path);
+        let job = restarted.dequeue().expect("queue should rehydrate from disk");
+        assert_eq!(job.filename, "a.jpg");
+
+        // next_job_id must have been restored too, so ids don't collide.
+        let id = restarted.enqueue("c.jpg".into(), "path/c.jpg.png".into(), false, 0, None, false);
+        assert_eq!(id, 3);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
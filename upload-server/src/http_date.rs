@@ -0,0 +1,93 @@
+//! Minimal HTTP-date (RFC 7231 `IMF-fixdate`) formatting and parsing, used by
+//! `image_serving` for `Last-Modified` / `If-Modified-Since`. Implemented
+//! by hand (civil-calendar math, not a date library) since the only thing
+//! needed is this one fixed wire format.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Converts a day count since the Unix epoch to a proleptic Gregorian
+/// (year, month, day). Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097); // [0, 146096]
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365; // [0, 399]
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100); // [0, 365]
+    let mp = (5 * doy + 2) / 153; // [0, 11]
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32; // [1, 31]
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32; // [1, 12]
+    let year = if m <= 2 { y + 1 } else { y };
+    (year, m, d)
+}
+
+/// Inverse of `civil_from_days`: (year, month, day) to a day count since the
+/// Unix epoch.
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400); // [0, 399]
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1; // [0, 365]
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy; // [0, 146096]
+    era * 146097 + doe - 719468
+}
+
+/// Formats `time` as an HTTP-date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+/// Sub-second precision is dropped, since that's all HTTP-date carries.
+pub fn format_http_date(time: SystemTime) -> String {
+    let total_secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let days = total_secs.div_euclid(86400);
+    let secs_of_day = total_secs.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let weekday = WEEKDAYS[((days.rem_euclid(7)) + 4).rem_euclid(7) as usize];
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        weekday,
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        secs_of_day / 3600,
+        (secs_of_day % 3600) / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Parses an HTTP-date back into a `SystemTime`. Only the IMF-fixdate shape
+/// this module emits (`"<wkday>, <day> <mon> <year> <hh>:<mm>:<ss> GMT"`) is
+/// supported; that's the only shape real browsers send in practice.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.trim().split_whitespace().collect();
+    let [_wkday, day, month, year, time, _gmt] = parts[..] else {
+        return None;
+    };
+
+    let day: u32 = day.parse().ok()?;
+    let month = (MONTHS.iter().position(|&m| m == month)? + 1) as u32;
+    let year: i64 = year.parse().ok()?;
+
+    let time_parts: Vec<&str> = time.split(':').collect();
+    let [hour, minute, second] = time_parts[..] else {
+        return None;
+    };
+    let hour: i64 = hour.parse().ok()?;
+    let minute: i64 = minute.parse().ok()?;
+    let second: i64 = second.parse().ok()?;
+
+    let days = days_from_civil(year, month, day);
+    let total_secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    if total_secs < 0 {
+        return None;
+    }
+
+    Some(UNIX_EPOCH + Duration::from_secs(total_secs as u64))
+}
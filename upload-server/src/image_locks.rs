@@ -2,12 +2,18 @@
 //!
 //! Provides exclusive edit access to images in detail view.
 //! Only one user can edit an image at a time.
+//!
+//! Locks are served through the `LockStore` trait so the process-local
+//! `MemoryLockStore` (the default) can be swapped for `RedisLockStore` when
+//! running more than one server instance against the same image library.
 
 use log::{debug, warn};
-use std::collections::HashMap;
+use rocket::async_trait;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
+use tokio::time;
 
 /// Lock duration before automatic expiry.
 /// Can be overridden via LOCK_DURATION_SECS environment variable for testing.
@@ -29,8 +35,61 @@ pub struct ImageLock {
     pub expires_at: Instant,
 }
 
-/// Shared state for image locks.
-pub type ImageLocksState = Arc<Mutex<HashMap<String, ImageLock>>>;
+/// Backend-agnostic image locking operations.
+///
+/// Implementations must provide "one editor at a time" semantics, including
+/// across process restarts and multiple server instances for distributed backends.
+#[async_trait]
+pub trait LockStore: Send + Sync {
+    /// Attempts to acquire (or, for the owning session, refresh) a lock.
+    /// When `refresh_only` is set, a missing lock is not acquired.
+    async fn try_acquire(
+        &self,
+        filename: &str,
+        session_id: &str,
+        refresh_only: bool,
+    ) -> Result<bool, String>;
+
+    /// Releases a lock. Only the owning session may release it.
+    async fn release(&self, filename: &str, session_id: &str) -> Result<bool, String>;
+
+    /// Checks whether `session_id` currently owns the lock on `filename`.
+    async fn verify_ownership(&self, filename: &str, session_id: &str) -> Result<bool, String>;
+
+    /// Returns the remaining lock duration in seconds, if the image is locked.
+    async fn remaining_secs(&self, filename: &str) -> Result<Option<u64>, String>;
+
+    /// Opt-in: joins the FIFO wait list for `filename` and returns the caller's
+    /// 1-based queue position. Backends that don't offer fair queueing can
+    /// leave this unimplemented.
+    async fn enqueue_for_lock(&self, _filename: &str, _session_id: &str) -> Result<usize, String> {
+        Err("queued lock acquisition not supported by this backend".to_string())
+    }
+
+    /// Opt-in: resolves once `session_id` reaches the front of the queue and is
+    /// granted the lock, or `timeout` elapses (returning `Ok(false)`).
+    async fn wait_for_lock(
+        &self,
+        _filename: &str,
+        _session_id: &str,
+        _timeout: Duration,
+    ) -> Result<bool, String> {
+        Err("queued lock acquisition not supported by this backend".to_string())
+    }
+
+    /// Opt-in: reports `session_id`'s current 1-based position in the wait list.
+    async fn queue_position(&self, _filename: &str, _session_id: &str) -> Option<usize> {
+        None
+    }
+}
+
+/// Shared state for image locks, backed by whichever `LockStore` impl is configured.
+pub type ImageLocksState = Arc<dyn LockStore>;
+
+/// Builds the default in-process lock backend.
+pub fn memory_backend() -> ImageLocksState {
+    Arc::new(MemoryLockStore::new())
+}
 
 /// Attempts to acquire a lock on an image.
 ///
@@ -42,55 +101,7 @@ pub async fn try_acquire_lock(
     session_id: &str,
     refresh_only: bool,
 ) -> Result<bool, String> {
-    let mut locks_map = locks.lock().await;
-
-    // Clean up expired locks first.
-    let now = Instant::now();
-    locks_map.retain(|_, lock| lock.expires_at > now);
-
-    // Check if image is already locked.
-    if let Some(existing_lock) = locks_map.get(filename) {
-        // Same session can refresh their own lock.
-        if existing_lock.session_id == session_id {
-            locks_map.insert(
-                filename.to_string(),
-                ImageLock {
-                    session_id: session_id.to_string(),
-                    expires_at: now + Duration::from_secs(get_lock_duration_secs()),
-                },
-            );
-            debug!("Lock refreshed: {} (session: {})", filename, session_id);
-            return Ok(true);
-        }
-
-        // Different session - lock is held by someone else.
-        let remaining = existing_lock.expires_at.saturating_duration_since(now);
-        debug!(
-            "Lock denied: {} already locked by {} (expires in {}s)",
-            filename,
-            existing_lock.session_id,
-            remaining.as_secs()
-        );
-        return Ok(false);
-    }
-
-    // Keepalive refresh should not reacquire a missing lock.
-    if refresh_only {
-        debug!("Lock refresh denied: {} has no active lock", filename);
-        return Ok(false);
-    }
-
-    // No existing lock - acquire it.
-    locks_map.insert(
-        filename.to_string(),
-        ImageLock {
-            session_id: session_id.to_string(),
-            expires_at: now + Duration::from_secs(get_lock_duration_secs()),
-        },
-    );
-
-    debug!("Lock acquired: {} (session: {})", filename, session_id);
-    Ok(true)
+    locks.try_acquire(filename, session_id, refresh_only).await
 }
 
 /// Releases a lock on an image.
@@ -101,24 +112,7 @@ pub async fn release_lock(
     filename: &str,
     session_id: &str,
 ) -> Result<bool, String> {
-    let mut locks_map = locks.lock().await;
-
-    if let Some(existing_lock) = locks_map.get(filename) {
-        if existing_lock.session_id == session_id {
-            locks_map.remove(filename);
-            debug!("Lock released: {} (session: {})", filename, session_id);
-            return Ok(true);
-        }
-
-        warn!(
-            "Lock release denied: {} owned by {}, requested by {}",
-            filename, existing_lock.session_id, session_id
-        );
-        return Ok(false);
-    }
-
-    // No lock exists - that's fine.
-    Ok(true)
+    locks.release(filename, session_id).await
 }
 
 /// Checks if a session owns the lock for an image.
@@ -127,31 +121,472 @@ pub async fn verify_lock_ownership(
     filename: &str,
     session_id: &str,
 ) -> Result<bool, String> {
-    let mut locks_map = locks.lock().await;
+    locks.verify_ownership(filename, session_id).await
+}
 
-    // Clean up expired locks first.
-    let now = Instant::now();
-    locks_map.retain(|_, lock| lock.expires_at > now);
+/// Gets remaining lock time in seconds for an image.
+pub async fn get_lock_remaining_secs(locks: &ImageLocksState, filename: &str) -> Option<u64> {
+    locks.remaining_secs(filename).await.unwrap_or(None)
+}
 
-    if let Some(existing_lock) = locks_map.get(filename) {
-        return Ok(existing_lock.session_id == session_id);
-    }
+/// Joins the FIFO wait list for an image and returns the caller's 1-based position.
+pub async fn enqueue_for_lock(
+    locks: &ImageLocksState,
+    filename: &str,
+    session_id: &str,
+) -> Result<usize, String> {
+    locks.enqueue_for_lock(filename, session_id).await
+}
 
-    // No lock exists - operation not allowed.
-    Ok(false)
+/// Waits until the caller reaches the front of the queue and is granted the lock,
+/// or `timeout` elapses.
+pub async fn wait_for_lock(
+    locks: &ImageLocksState,
+    filename: &str,
+    session_id: &str,
+    timeout: Duration,
+) -> Result<bool, String> {
+    locks.wait_for_lock(filename, session_id, timeout).await
 }
 
-/// Gets remaining lock time in seconds for an image.
-pub async fn get_lock_remaining_secs(
+/// Reports a session's current position in an image's wait list, if any.
+pub async fn get_queue_position(
     locks: &ImageLocksState,
     filename: &str,
-) -> Option<u64> {
-    let locks_map = locks.lock().await;
-    let now = Instant::now();
-
-    locks_map.get(filename).map(|lock| {
-        lock.expires_at
-            .saturating_duration_since(now)
-            .as_secs()
-    })
+    session_id: &str,
+) -> Option<usize> {
+    locks.queue_position(filename, session_id).await
+}
+
+/// FIFO wait list for a single image, plus the means to wake waiters when the
+/// lock is released or expires.
+struct LockQueue {
+    waiters: VecDeque<String>,
+    /// Last keepalive per waiting session, used to prune abandoned waiters.
+    keepalive: HashMap<String, Instant>,
+    notify: Arc<Notify>,
+}
+
+impl LockQueue {
+    fn new() -> Self {
+        Self {
+            waiters: VecDeque::new(),
+            keepalive: HashMap::new(),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+}
+
+/// Default in-process lock backend, holding locks in an `Arc<Mutex<HashMap<...>>>`.
+/// Does not survive process restarts and only coordinates within a single instance.
+pub struct MemoryLockStore {
+    locks: Mutex<HashMap<String, ImageLock>>,
+    queues: Mutex<HashMap<String, LockQueue>>,
+}
+
+impl MemoryLockStore {
+    pub fn new() -> Self {
+        Self {
+            locks: Mutex::new(HashMap::new()),
+            queues: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Prunes waiters whose keepalive has lapsed, then grants the lock to the
+    /// next (still-alive) waiter in line, if any. Called whenever a lock frees up.
+    async fn grant_next_waiter(&self, filename: &str) {
+        let mut queues = self.queues.lock().await;
+        let Some(queue) = queues.get_mut(filename) else { return };
+
+        let now = Instant::now();
+        let keepalive_window = Duration::from_secs(get_lock_duration_secs());
+
+        while let Some(front) = queue.waiters.front() {
+            let alive = queue
+                .keepalive
+                .get(front)
+                .is_some_and(|last| now.duration_since(*last) < keepalive_window);
+
+            if alive {
+                break;
+            }
+
+            let abandoned = queue.waiters.pop_front().unwrap();
+            queue.keepalive.remove(&abandoned);
+            debug!("Pruned abandoned lock waiter: {} for {}", abandoned, filename);
+        }
+
+        let Some(next_session) = queue.waiters.pop_front() else { return };
+        queue.keepalive.remove(&next_session);
+        let notify = queue.notify.clone();
+
+        let mut locks_map = self.locks.lock().await;
+        locks_map.insert(
+            filename.to_string(),
+            ImageLock {
+                session_id: next_session.clone(),
+                expires_at: now + keepalive_window,
+            },
+        );
+        drop(locks_map);
+
+        debug!("Lock granted from queue: {} (session: {})", filename, next_session);
+        notify.notify_waiters();
+    }
+}
+
+impl Default for MemoryLockStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl LockStore for MemoryLockStore {
+    async fn try_acquire(
+        &self,
+        filename: &str,
+        session_id: &str,
+        refresh_only: bool,
+    ) -> Result<bool, String> {
+        let mut locks_map = self.locks.lock().await;
+
+        // Clean up expired locks first, remembering which images freed up so
+        // their wait queue (if any) can be granted the lock below.
+        let now = Instant::now();
+        let expired: Vec<String> = locks_map
+            .iter()
+            .filter(|(_, lock)| lock.expires_at <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        locks_map.retain(|_, lock| lock.expires_at > now);
+        drop(locks_map);
+        for name in &expired {
+            self.grant_next_waiter(name).await;
+        }
+        let mut locks_map = self.locks.lock().await;
+
+        // Check if image is already locked.
+        if let Some(existing_lock) = locks_map.get(filename) {
+            // Same session can refresh their own lock.
+            if existing_lock.session_id == session_id {
+                locks_map.insert(
+                    filename.to_string(),
+                    ImageLock {
+                        session_id: session_id.to_string(),
+                        expires_at: now + Duration::from_secs(get_lock_duration_secs()),
+                    },
+                );
+                debug!("Lock refreshed: {} (session: {})", filename, session_id);
+                return Ok(true);
+            }
+
+            // Different session - lock is held by someone else.
+            let remaining = existing_lock.expires_at.saturating_duration_since(now);
+            debug!(
+                "Lock denied: {} already locked by {} (expires in {}s)",
+                filename,
+                existing_lock.session_id,
+                remaining.as_secs()
+            );
+            return Ok(false);
+        }
+
+        // Keepalive refresh should not reacquire a missing lock.
+        if refresh_only {
+            debug!("Lock refresh denied: {} has no active lock", filename);
+            return Ok(false);
+        }
+
+        // No existing lock - acquire it.
+        locks_map.insert(
+            filename.to_string(),
+            ImageLock {
+                session_id: session_id.to_string(),
+                expires_at: now + Duration::from_secs(get_lock_duration_secs()),
+            },
+        );
+
+        debug!("Lock acquired: {} (session: {})", filename, session_id);
+        Ok(true)
+    }
+
+    async fn release(&self, filename: &str, session_id: &str) -> Result<bool, String> {
+        let mut locks_map = self.locks.lock().await;
+
+        if let Some(existing_lock) = locks_map.get(filename) {
+            if existing_lock.session_id == session_id {
+                locks_map.remove(filename);
+                drop(locks_map);
+                debug!("Lock released: {} (session: {})", filename, session_id);
+                self.grant_next_waiter(filename).await;
+                return Ok(true);
+            }
+
+            warn!(
+                "Lock release denied: {} owned by {}, requested by {}",
+                filename, existing_lock.session_id, session_id
+            );
+            return Ok(false);
+        }
+
+        // No lock exists - that's fine.
+        Ok(true)
+    }
+
+    async fn verify_ownership(&self, filename: &str, session_id: &str) -> Result<bool, String> {
+        let mut locks_map = self.locks.lock().await;
+
+        // Clean up expired locks first.
+        let now = Instant::now();
+        let expired: Vec<String> = locks_map
+            .iter()
+            .filter(|(_, lock)| lock.expires_at <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        locks_map.retain(|_, lock| lock.expires_at > now);
+        drop(locks_map);
+        for name in &expired {
+            self.grant_next_waiter(name).await;
+        }
+        let locks_map = self.locks.lock().await;
+
+        if let Some(existing_lock) = locks_map.get(filename) {
+            return Ok(existing_lock.session_id == session_id);
+        }
+
+        // No lock exists - operation not allowed.
+        Ok(false)
+    }
+
+    async fn remaining_secs(&self, filename: &str) -> Result<Option<u64>, String> {
+        let locks_map = self.locks.lock().await;
+        let now = Instant::now();
+
+        Ok(locks_map
+            .get(filename)
+            .map(|lock| lock.expires_at.saturating_duration_since(now).as_secs()))
+    }
+
+    async fn enqueue_for_lock(&self, filename: &str, session_id: &str) -> Result<usize, String> {
+        let mut queues = self.queues.lock().await;
+        let queue = queues.entry(filename.to_string()).or_insert_with(LockQueue::new);
+
+        if !queue.waiters.iter().any(|s| s == session_id) {
+            queue.waiters.push_back(session_id.to_string());
+        }
+        queue.keepalive.insert(session_id.to_string(), Instant::now());
+
+        Ok(queue
+            .waiters
+            .iter()
+            .position(|s| s == session_id)
+            .map(|pos| pos + 1)
+            .unwrap_or(queue.waiters.len()))
+    }
+
+    async fn wait_for_lock(
+        &self,
+        filename: &str,
+        session_id: &str,
+        timeout: Duration,
+    ) -> Result<bool, String> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if self.verify_ownership(filename, session_id).await? {
+                return Ok(true);
+            }
+
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return Ok(false);
+            };
+
+            let notify = {
+                let mut queues = self.queues.lock().await;
+                queues
+                    .entry(filename.to_string())
+                    .or_insert_with(LockQueue::new)
+                    .notify
+                    .clone()
+            };
+
+            if time::timeout(remaining, notify.notified()).await.is_err() {
+                return Ok(false);
+            }
+        }
+    }
+
+    async fn queue_position(&self, filename: &str, session_id: &str) -> Option<usize> {
+        let queues = self.queues.lock().await;
+        queues
+            .get(filename)?
+            .waiters
+            .iter()
+            .position(|s| s == session_id)
+            .map(|pos| pos + 1)
+    }
+}
+
+/// Redis-backed lock store for multi-instance deployments.
+///
+/// Each image maps to a key holding the owning `session_id` with a TTL equal to
+/// the lock duration, so locks survive process restarts and are visible to
+/// every server instance pointed at the same Redis.
+pub struct RedisLockStore {
+    client: redis::Client,
+}
+
+const LOCK_KEY_PREFIX: &str = "inky-soup:lock:";
+
+/// Releases a lock only if the caller's session still owns it (compare-and-delete).
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+else
+    return 0
+end
+"#;
+
+/// Refreshes a lock's TTL only if the caller's session still owns it.
+const REFRESH_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+    return 1
+else
+    return 0
+end
+"#;
+
+impl RedisLockStore {
+    pub fn new(redis_url: &str) -> Result<Self, String> {
+        let client =
+            redis::Client::open(redis_url).map_err(|e| format!("Failed to connect to Redis: {}", e))?;
+        Ok(Self { client })
+    }
+
+    fn lock_key(filename: &str) -> String {
+        format!("{}{}", LOCK_KEY_PREFIX, filename)
+    }
+
+    async fn connection(&self) -> Result<redis::aio::MultiplexedConnection, String> {
+        self.client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| format!("Redis connection error: {}", e))
+    }
+}
+
+#[async_trait]
+impl LockStore for RedisLockStore {
+    async fn try_acquire(
+        &self,
+        filename: &str,
+        session_id: &str,
+        refresh_only: bool,
+    ) -> Result<bool, String> {
+        let mut conn = self.connection().await?;
+        let key = Self::lock_key(filename);
+        let ttl = get_lock_duration_secs();
+
+        if refresh_only {
+            let refreshed: i64 = redis::Script::new(REFRESH_SCRIPT)
+                .key(&key)
+                .arg(session_id)
+                .arg(ttl)
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| format!("Redis refresh error: {}", e))?;
+            return Ok(refreshed == 1);
+        }
+
+        // SET key session_id NX EX <secs>: only acquires if nobody holds it.
+        let acquired: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(session_id)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| format!("Redis acquire error: {}", e))?;
+
+        if acquired.is_some() {
+            debug!("Lock acquired (redis): {} (session: {})", filename, session_id);
+            return Ok(true);
+        }
+
+        // Already held - if it's ours, refresh it; otherwise it's held by someone else.
+        let refreshed: i64 = redis::Script::new(REFRESH_SCRIPT)
+            .key(&key)
+            .arg(session_id)
+            .arg(ttl)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| format!("Redis refresh error: {}", e))?;
+
+        Ok(refreshed == 1)
+    }
+
+    async fn release(&self, filename: &str, session_id: &str) -> Result<bool, String> {
+        let mut conn = self.connection().await?;
+        let key = Self::lock_key(filename);
+
+        let deleted: i64 = redis::Script::new(RELEASE_SCRIPT)
+            .key(&key)
+            .arg(session_id)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| format!("Redis release error: {}", e))?;
+
+        if deleted == 1 {
+            debug!("Lock released (redis): {} (session: {})", filename, session_id);
+            return Ok(true);
+        }
+
+        // Either there was no lock, or it's owned by someone else.
+        let owner: Option<String> = redis::cmd("GET")
+            .arg(&key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| format!("Redis get error: {}", e))?;
+
+        match owner {
+            None => Ok(true), // No lock exists - that's fine.
+            Some(owner) => {
+                warn!(
+                    "Lock release denied: {} owned by {}, requested by {}",
+                    filename, owner, session_id
+                );
+                Ok(false)
+            }
+        }
+    }
+
+    async fn verify_ownership(&self, filename: &str, session_id: &str) -> Result<bool, String> {
+        let mut conn = self.connection().await?;
+        let key = Self::lock_key(filename);
+
+        let owner: Option<String> = redis::cmd("GET")
+            .arg(&key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| format!("Redis get error: {}", e))?;
+
+        Ok(owner.as_deref() == Some(session_id))
+    }
+
+    async fn remaining_secs(&self, filename: &str) -> Result<Option<u64>, String> {
+        let mut conn = self.connection().await?;
+        let key = Self::lock_key(filename);
+
+        let ttl: i64 = redis::cmd("TTL")
+            .arg(&key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| format!("Redis ttl error: {}", e))?;
+
+        // TTL returns -2 (no such key) or -1 (no expiry set) for an unlocked image.
+        Ok(if ttl >= 0 { Some(ttl as u64) } else { None })
+    }
 }
@@ -0,0 +1,304 @@
+//! Conditional-GET, cache-control, and HTTP Range support for served images.
+//!
+//! Rocket's `FileServer` (still mounted for everything else under `static/`)
+//! has no validators: browsers refetch full originals and thumbnails on
+//! every gallery load, and large originals can't be partially fetched. This
+//! route takes over `/images/<path..>` ahead of `FileServer` (explicit
+//! routes rank above it) to add `Last-Modified` / `If-Modified-Since` /
+//! `304 Not Modified`, long `Cache-Control` for the derived (cache, dithered,
+//! thumbs) trees, and `Range` / `Accept-Ranges: bytes` for originals, which
+//! are the only files here large enough for partial fetches to matter.
+
+use crate::http_date;
+use crate::store::{ImageStore, ImageStores};
+use rocket::http::{ContentType, Status};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::Response;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// The parsed `If-Modified-Since` request header, if present and well-formed.
+pub struct IfModifiedSince(Option<SystemTime>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfModifiedSince {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let value = req
+            .headers()
+            .get_one("If-Modified-Since")
+            .and_then(http_date::parse_http_date);
+        Outcome::Success(IfModifiedSince(value))
+    }
+}
+
+/// A parsed single-range `Range` request header. The three forms
+/// (`bytes=<start>-<end>`, `bytes=<start>-`, `bytes=-<suffix_length>`) are
+/// kept as distinct variants rather than collapsing to `(start, Option<end>)`
+/// - that tuple can't tell `bytes=0-499` (first 500 bytes) apart from
+/// `bytes=-499` (last 499 bytes), since both have a start of 0. Multi-range
+/// requests aren't supported; callers treat this as "no range" and serve the
+/// whole file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeSpec {
+    Range { start: u64, end: u64 },
+    SuffixLength(u64),
+    Open { start: u64 },
+}
+
+pub struct RangeHeader(Option<RangeSpec>);
+
+/// Parses a `Range` header's value (without the leading `bytes=`) into a
+/// `RangeSpec`. Split out from `FromRequest::from_request` so the three
+/// forms, especially the `start`-is-empty-vs-`end`-is-empty distinction, are
+/// unit-testable without a full `Request`.
+fn parse_range_spec(raw: &str) -> Option<RangeSpec> {
+    let spec = raw.strip_prefix("bytes=").filter(|spec| !spec.contains(','))?;
+    let (start, end) = spec.split_once('-')?;
+
+    if start.is_empty() {
+        return end.parse::<u64>().ok().map(RangeSpec::SuffixLength);
+    }
+
+    let start: u64 = start.parse().ok()?;
+    if end.is_empty() {
+        return Some(RangeSpec::Open { start });
+    }
+
+    let end: u64 = end.parse().ok()?;
+    Some(RangeSpec::Range { start, end })
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeHeader {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let value = req.headers().get_one("Range").and_then(parse_range_spec);
+        Outcome::Success(RangeHeader(value))
+    }
+}
+
+/// Directories whose contents are re-rendered in place under the same
+/// filename, so a long `Cache-Control` still needs a `Last-Modified` check
+/// rather than `immutable`.
+const DERIVED_PREFIXES: &[&str] = &["cache/", "dithered/", "thumbs/"];
+
+fn is_derived(relative: &str) -> bool {
+    DERIVED_PREFIXES.iter().any(|prefix| relative.starts_with(prefix))
+}
+
+/// Maps a `/images/<path..>` URL path to the store that owns it and the key
+/// within that store. Cache/dithered/thumbs live under a subdirectory
+/// prefix; originals are flat, so anything else with no further nesting is
+/// an original. Returns `None` for paths that don't resolve to any tree
+/// (e.g. `metadata/`, which isn't served here).
+fn store_for<'a>(relative: &str, stores: &'a ImageStores) -> Option<(&'a Arc<dyn ImageStore>, String)> {
+    for (prefix, store) in [
+        ("cache/", &stores.cache),
+        ("dithered/", &stores.dithered),
+        ("thumbs/", &stores.thumbs),
+    ] {
+        if let Some(key) = relative.strip_prefix(prefix) {
+            return Some((store, key.to_string()));
+        }
+    }
+
+    if relative.is_empty() || relative.contains('/') {
+        return None;
+    }
+
+    Some((&stores.originals, relative.to_string()))
+}
+
+/// Serves an image through `ImageStores` with conditional-GET, cache-control,
+/// and (for originals) Range support, so this works the same whether the
+/// backing store is the local filesystem or S3. Called from the
+/// `/images/<path..>` route in `main.rs`, which owns all of this crate's
+/// route declarations.
+pub async fn respond(
+    path: PathBuf,
+    if_modified_since: IfModifiedSince,
+    range: RangeHeader,
+    stores: &ImageStores,
+) -> Result<Response<'static>, Status> {
+    let relative = path.to_str().ok_or(Status::BadRequest)?;
+    if relative.contains("..") {
+        return Err(Status::BadRequest);
+    }
+
+    let (store, key) = store_for(relative, stores).ok_or(Status::NotFound)?;
+
+    if !store.exists(&key).await {
+        return Err(Status::NotFound);
+    }
+
+    let is_original = !is_derived(relative);
+
+    // `cache_worker::enforce_cache_budget`'s LRU/grace-window eviction only
+    // means anything if it reflects real traffic, not just the background
+    // worker's own writes - record every real request here rather than
+    // routing reads back through `cache_worker`, which would reintroduce a
+    // local-disk-only path this function was rewritten to not depend on.
+    if relative.starts_with("cache/") {
+        crate::cache_worker::record_cache_access(&key);
+    } else if is_original {
+        crate::cache_worker::record_original_access(&key);
+    }
+
+    let stat = store.stat(&key).await.map_err(|_| Status::InternalServerError)?;
+
+    let last_modified = http_date::format_http_date(stat.last_accessed);
+    // HTTP-date has only second resolution, so compare against a
+    // round-tripped value rather than the raw `SystemTime`.
+    let modified_rounded = http_date::parse_http_date(&last_modified).unwrap_or(stat.last_accessed);
+    let cache_control = if is_original {
+        "public, max-age=86400"
+    } else {
+        "public, max-age=31536000"
+    };
+
+    let content_type = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary);
+
+    if let IfModifiedSince(Some(since)) = if_modified_since {
+        if modified_rounded <= since {
+            return Ok(Response::build()
+                .status(Status::NotModified)
+                .raw_header("Last-Modified", last_modified)
+                .raw_header("Cache-Control", cache_control)
+                .finalize());
+        }
+    }
+
+    // The cache tree is written by a background worker that can still be
+    // mid-write when a request lands; `read_cache_file` waits for that write
+    // to finish first so a reader never observes a truncated PNG. The other
+    // trees are written synchronously within a single request and don't need
+    // that coordination.
+    let bytes = if relative.starts_with("cache/") {
+        crate::cache_worker::read_cache_file(&key, store).await.map_err(|_| Status::InternalServerError)?
+    } else {
+        store.get(&key).await.map_err(|_| Status::InternalServerError)?
+    };
+    let file_len = bytes.len() as u64;
+
+    if is_original {
+        if let RangeHeader(Some(spec)) = range {
+            return serve_range(bytes, file_len, spec, content_type, &last_modified, cache_control);
+        }
+    }
+
+    let mut builder = Response::build();
+    builder
+        .status(Status::Ok)
+        .header(content_type)
+        .raw_header("Last-Modified", last_modified)
+        .raw_header("Cache-Control", cache_control)
+        .sized_body(bytes.len(), Cursor::new(bytes));
+
+    if is_original {
+        builder.raw_header("Accept-Ranges", "bytes");
+    }
+
+    Ok(builder.finalize())
+}
+
+/// Builds a `206 Partial Content` (or `416 Range Not Satisfiable`) response
+/// for a single-range request against an original image. `ImageStore` has no
+/// partial-read primitive, so the whole object is fetched and sliced in
+/// memory - originals are the only tree this applies to, and large ones are
+/// exactly the case Range exists for, but a backend-agnostic store can't
+/// seek a remote object without fetching it first.
+fn serve_range(
+    bytes: Vec<u8>,
+    file_len: u64,
+    spec: RangeSpec,
+    content_type: ContentType,
+    last_modified: &str,
+    cache_control: &str,
+) -> Result<Response<'static>, Status> {
+    let (start, end) = match spec {
+        RangeSpec::Range { start, end } => (start, end.min(file_len.saturating_sub(1))),
+        RangeSpec::Open { start } => (start, file_len.saturating_sub(1)),
+        RangeSpec::SuffixLength(suffix_len) => {
+            if suffix_len >= file_len {
+                (0, file_len.saturating_sub(1))
+            } else {
+                (file_len - suffix_len, file_len.saturating_sub(1))
+            }
+        }
+    };
+
+    if file_len == 0 || start > end || start >= file_len {
+        return Ok(Response::build()
+            .status(Status::RangeNotSatisfiable)
+            .raw_header("Content-Range", format!("bytes */{}", file_len))
+            .finalize());
+    }
+
+    let length = (end - start + 1) as usize;
+    let buf = bytes[start as usize..start as usize + length].to_vec();
+
+    Ok(Response::build()
+        .status(Status::PartialContent)
+        .header(content_type)
+        .raw_header("Last-Modified", last_modified.to_string())
+        .raw_header("Cache-Control", cache_control.to_string())
+        .raw_header("Accept-Ranges", "bytes")
+        .raw_header("Content-Range", format!("bytes {}-{}/{}", start, end, file_len))
+        .sized_body(buf.len(), Cursor::new(buf))
+        .finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prefix_range() {
+        // `bytes=0-499`: the first 500 bytes, not to be confused with the
+        // suffix form below despite both having a zero start.
+        assert_eq!(parse_range_spec("bytes=0-499"), Some(RangeSpec::Range { start: 0, end: 499 }));
+    }
+
+    #[test]
+    fn test_parse_suffix_range() {
+        // `bytes=-499`: the last 499 bytes.
+        assert_eq!(parse_range_spec("bytes=-499"), Some(RangeSpec::SuffixLength(499)));
+    }
+
+    #[test]
+    fn test_parse_open_range() {
+        assert_eq!(parse_range_spec("bytes=500-"), Some(RangeSpec::Open { start: 500 }));
+    }
+
+    #[test]
+    fn test_parse_rejects_multi_range() {
+        assert_eq!(parse_range_spec("bytes=0-1,2-3"), None);
+    }
+
+    #[test]
+    fn test_prefix_range_serves_first_bytes_not_last() {
+        let bytes = b"0123456789".to_vec();
+        let response = serve_range(
+            bytes,
+            10,
+            RangeSpec::Range { start: 0, end: 4 },
+            ContentType::Binary,
+            "last-modified",
+            "cache-control",
+        )
+        .unwrap();
+        assert_eq!(response.status(), Status::PartialContent);
+        let content_range = response.headers().get_one("Content-Range").unwrap();
+        assert_eq!(content_range, "bytes 0-4/10");
+    }
+}
@@ -0,0 +1,52 @@
+//! Streaming SHA-256 hashing of an uploaded file while it's staged to disk.
+//!
+//! Mirrors pict-rs's ingest hasher: rather than copying a `TempFile` to its
+//! staging path and separately decoding it to hash the decoded pixels
+//! (`processor::content_hash`, which still runs afterward to catch the same
+//! picture re-encoded or renamed), hash each chunk as it's read and written
+//! so a byte-identical re-upload is caught in one disk pass, before paying
+//! for a decode at all.
+
+use rocket::fs::TempFile;
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Copies `file` to `staging_path`, returning the hex-encoded SHA-256 of its
+/// raw bytes, computed in the same pass as the copy.
+pub async fn stage_and_hash(file: &mut TempFile<'_>, staging_path: &str) -> Result<String, String> {
+    let mut reader = file
+        .open()
+        .await
+        .map_err(|e| format!("failed to open upload for staging: {}", e))?;
+    let mut writer = File::create(staging_path)
+        .await
+        .map_err(|e| format!("failed to create staging file '{}': {}", staging_path, e))?;
+
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; CHUNK_SIZE];
+
+    loop {
+        let read = reader
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("failed to read upload: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        writer
+            .write_all(&buf[..read])
+            .await
+            .map_err(|e| format!("failed to write staging file '{}': {}", staging_path, e))?;
+    }
+
+    writer
+        .flush()
+        .await
+        .map_err(|e| format!("failed to flush staging file '{}': {}", staging_path, e))?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,345 @@
+//! Background job subsystem for long-running, resumable maintenance tasks.
+//!
+//! A `Job` is assembled with `JobBuilder`, assigned a stable id, and persists
+//! its progress to a `JobReport` JSON file (one per job, atomically written
+//! like `metadata.rs`) after every work item. An interrupted run resumes from
+//! its last completed item on the next startup instead of starting over.
+//! Per-item failures are collected as warnings on the report rather than
+//! aborting the whole job.
+//!
+//! Currently the only job kind is "rebuild derived assets", which re-creates
+//! the display-resolution cache image for every original using its saved
+//! `ImageMetadata`. Thumbnails and dithered images are not regenerated by
+//! this job; a missing one is recorded as a warning rather than an error.
+
+use crate::{cache_worker, config, metadata};
+use glob::glob;
+use log::{error, info, warn};
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// Shared registry of running jobs, used to look up a job's cancellation flag.
+/// Finished jobs are dropped from the registry; their `JobReport` lives on disk.
+pub type JobsState = Arc<Mutex<HashMap<u64, Arc<AtomicBool>>>>;
+
+pub fn new_jobs_state() -> JobsState {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// Persisted progress for a single job, written to disk after each work item.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct JobReport {
+    pub job_id: u64,
+    pub status: JobStatus,
+    pub total: usize,
+    pub completed: usize,
+    pub current_filename: Option<String>,
+    /// Non-fatal per-item failures, e.g. "foo.jpg: missing thumbnail".
+    pub warnings: Vec<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// A built job, ready to run: a report plus the work items still left to process.
+pub struct Job {
+    report: JobReport,
+    remaining: Vec<String>,
+}
+
+/// Builds a `Job` for a fresh run, or resumes one from a persisted `JobReport`.
+pub struct JobBuilder {
+    job_id: u64,
+    items: Vec<String>,
+}
+
+impl JobBuilder {
+    pub fn new(job_id: u64) -> Self {
+        Self {
+            job_id,
+            items: Vec::new(),
+        }
+    }
+
+    pub fn with_items(mut self, items: Vec<String>) -> Self {
+        self.items = items;
+        self
+    }
+
+    /// Builds a fresh job starting at item 0.
+    pub fn build(self) -> Job {
+        let now = current_time_millis();
+        Job {
+            report: JobReport {
+                job_id: self.job_id,
+                status: JobStatus::Running,
+                total: self.items.len(),
+                completed: 0,
+                current_filename: None,
+                warnings: Vec::new(),
+                created_at: now,
+                updated_at: now,
+            },
+            remaining: self.items,
+        }
+    }
+
+    /// Resumes a job from a previously persisted report, skipping the items
+    /// it had already completed.
+    pub fn resume(self, report: JobReport) -> Job {
+        let remaining = self.items.into_iter().skip(report.completed).collect();
+        Job {
+            report,
+            remaining,
+        }
+    }
+}
+
+fn jobs_dir() -> PathBuf {
+    config::data_dir().join("jobs")
+}
+
+fn ensure_jobs_dir() {
+    let path = jobs_dir();
+    if !path.exists() {
+        if let Err(e) = fs::create_dir_all(&path) {
+            error!("Failed to create jobs directory: {}", e);
+        }
+    }
+}
+
+fn report_path(job_id: u64) -> PathBuf {
+    jobs_dir().join(format!("{}.json", job_id))
+}
+
+/// Atomically writes a job's report to disk (temp file + rename).
+fn persist_report(report: &JobReport) {
+    ensure_jobs_dir();
+
+    let path = report_path(report.job_id);
+    let temp_path = path.with_extension("json.tmp");
+
+    match serde_json::to_string_pretty(report) {
+        Ok(json) => {
+            if let Err(e) = fs::write(&temp_path, &json) {
+                error!("Failed to write temp report for job {}: {}", report.job_id, e);
+                return;
+            }
+
+            if let Err(e) = fs::rename(&temp_path, &path) {
+                error!("Failed to rename report file for job {}: {}", report.job_id, e);
+                let _ = fs::remove_file(&temp_path);
+            }
+        }
+        Err(e) => {
+            error!("Failed to serialize report for job {}: {}", report.job_id, e);
+        }
+    }
+}
+
+/// Loads a single job's persisted report, if it exists.
+pub fn get_job_report(job_id: u64) -> Option<JobReport> {
+    let path = report_path(job_id);
+    let contents = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Loads every persisted job report, in no particular order.
+fn list_reports() -> Vec<JobReport> {
+    let Ok(entries) = fs::read_dir(jobs_dir()) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let contents = fs::read_to_string(entry.path()).ok()?;
+            serde_json::from_str(&contents).ok()
+        })
+        .collect()
+}
+
+/// Picks the next job id by taking the highest persisted id and adding one.
+fn next_job_id() -> u64 {
+    list_reports()
+        .iter()
+        .map(|r| r.job_id)
+        .max()
+        .map(|id| id + 1)
+        .unwrap_or(1)
+}
+
+/// Lists original image filenames the same way `get_gallery_images` does,
+/// skipping directories and the legacy metadata file.
+fn list_original_filenames() -> Vec<String> {
+    let pattern = format!("{}/*", config::IMAGES_DIR.display());
+    let Ok(paths) = glob(&pattern) else {
+        error!("Failed to read glob pattern for {}", pattern);
+        return Vec::new();
+    };
+
+    paths
+        .flatten()
+        .filter(|path| !path.is_dir())
+        .filter_map(|path| path.file_name()?.to_str().map(|s| s.to_string()))
+        .filter(|filename| !filename.starts_with("metadata.json"))
+        .collect()
+}
+
+/// Regenerates the display-resolution cache image for one original from its
+/// saved `ImageMetadata`. Thumbnail and dithered outputs are not regenerated;
+/// a missing one is reported as a warning rather than an error.
+async fn rebuild_one(filename: &str) -> Result<(), String> {
+    let meta = metadata::get_all_metadata(filename);
+    let filter = metadata::parse_filter(&meta.filter);
+    let original_path = config::original_path(filename);
+
+    let result = tokio::task::spawn_blocking(move || cache_worker::create_cached_image(&original_path, filter))
+        .await
+        .map_err(|e| format!("cache regeneration task panicked: {}", e))?;
+    result?;
+
+    let mut missing = Vec::new();
+    if !config::thumb_path(filename).exists() {
+        missing.push("thumbnail");
+    }
+    if !config::dithered_path(filename).exists() {
+        missing.push("dithered image");
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "cache rebuilt, but {} missing (not regenerated by this job)",
+            missing.join(" and ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Runs a job to completion (or cancellation), persisting the report after
+/// every item so an interrupted run can resume from where it stopped.
+async fn run_rebuild_derived_assets(mut job: Job, cancel: Arc<AtomicBool>, jobs_state: JobsState) {
+    info!(
+        "Job {}: rebuilding derived assets for {} remaining image(s)",
+        job.report.job_id,
+        job.remaining.len()
+    );
+
+    for filename in std::mem::take(&mut job.remaining) {
+        if cancel.load(Ordering::SeqCst) {
+            job.report.status = JobStatus::Cancelled;
+            job.report.current_filename = None;
+            job.report.updated_at = current_time_millis();
+            persist_report(&job.report);
+            info!(
+                "Job {}: cancelled after {}/{} item(s)",
+                job.report.job_id, job.report.completed, job.report.total
+            );
+            jobs_state.lock().await.remove(&job.report.job_id);
+            return;
+        }
+
+        job.report.current_filename = Some(filename.clone());
+        persist_report(&job.report);
+
+        if let Err(e) = rebuild_one(&filename).await {
+            warn!("Job {}: '{}': {}", job.report.job_id, filename, e);
+            job.report.warnings.push(format!("{}: {}", filename, e));
+        }
+
+        job.report.completed += 1;
+        job.report.updated_at = current_time_millis();
+        persist_report(&job.report);
+    }
+
+    job.report.current_filename = None;
+    job.report.status = JobStatus::Completed;
+    job.report.updated_at = current_time_millis();
+    persist_report(&job.report);
+    jobs_state.lock().await.remove(&job.report.job_id);
+
+    info!(
+        "Job {}: completed, {}/{} item(s), {} warning(s)",
+        job.report.job_id,
+        job.report.completed,
+        job.report.total,
+        job.report.warnings.len()
+    );
+}
+
+/// Builds, registers, and spawns a fresh "rebuild derived assets" job.
+/// Returns the new job's id immediately; the job runs in the background.
+pub async fn enqueue_rebuild_derived_assets_job(jobs_state: &JobsState) -> u64 {
+    let job_id = next_job_id();
+    let job = JobBuilder::new(job_id)
+        .with_items(list_original_filenames())
+        .build();
+
+    persist_report(&job.report);
+
+    let cancel = Arc::new(AtomicBool::new(false));
+    jobs_state.lock().await.insert(job_id, cancel.clone());
+
+    tokio::spawn(run_rebuild_derived_assets(job, cancel, jobs_state.clone()));
+
+    job_id
+}
+
+/// Requests cancellation of a running job. Returns `false` if the job isn't
+/// currently running (already finished, or never existed).
+pub async fn cancel_job(jobs_state: &JobsState, job_id: u64) -> bool {
+    if let Some(cancel) = jobs_state.lock().await.get(&job_id) {
+        cancel.store(true, Ordering::SeqCst);
+        true
+    } else {
+        false
+    }
+}
+
+/// Resumes every job left in `Running` state from a prior run (e.g. the
+/// server was restarted mid-job), continuing from its last completed item.
+pub async fn resume_interrupted_jobs(jobs_state: JobsState) {
+    let interrupted: Vec<JobReport> = list_reports()
+        .into_iter()
+        .filter(|r| r.status == JobStatus::Running)
+        .collect();
+
+    for report in interrupted {
+        info!(
+            "Resuming job {} after restart ({}/{} item(s) already done)",
+            report.job_id, report.completed, report.total
+        );
+
+        let job_id = report.job_id;
+        let job = JobBuilder::new(job_id)
+            .with_items(list_original_filenames())
+            .resume(report);
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        jobs_state.lock().await.insert(job_id, cancel.clone());
+
+        tokio::spawn(run_rebuild_derived_assets(job, cancel, jobs_state.clone()));
+    }
+}
+
+fn current_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
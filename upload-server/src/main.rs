@@ -1,12 +1,25 @@
 #[macro_use] extern crate rocket;
 
+mod blurhash;
 mod cache_worker;
 mod cleanup;
+mod config;
+mod derived_manifest;
+mod display_settings;
+mod dither;
 mod flash_queue;
+mod http_date;
 mod image_locks;
+mod image_serving;
+mod ingest;
+mod jobs;
 mod metadata;
+mod phash;
+mod processor;
+mod store;
+mod upload_queue;
+mod validate;
 
-use glob::glob;
 use log::{debug, error, info, warn};
 
 use rocket_dyn_templates::Template;
@@ -14,19 +27,23 @@ use rocket::fairing::{Fairing, Info, Kind};
 use rocket::form::{Form, Contextual};
 use rocket::fs::{FileServer, TempFile};
 use rocket::http::Status;
-use rocket::response::Redirect;
+use rocket::response::stream::{Event, EventStream};
+use rocket::response::{Redirect, Response};
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
 use rocket::{Rocket, State};
 
-use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use tokio::sync::broadcast;
 use tokio::sync::Mutex;
 
-use flash_queue::{FlashJob, FlashQueue, FlashQueueState};
+use flash_queue::{FlashEvent, FlashJob, FlashQueue, FlashQueueState, FlashWorkerHandle};
 use image_locks::ImageLocksState;
+use jobs::{JobReport, JobsState};
+use store::ImageStores;
+use upload_queue::{UploadJob, UploadQueueState};
 
 
 #[derive(Debug, FromForm)]
@@ -40,6 +57,9 @@ struct FlashSubmission {
     image_file_path: String,
     session_id: String,
     flash_twice: bool,
+    /// Requests a fresh server-side dither even if a dithered image is
+    /// already present at `image_file_path`.
+    force_dither: bool,
 }
 
 #[derive(Debug, FromForm)]
@@ -77,6 +97,8 @@ struct GalleryImage {
     brightness: i32,
     contrast: i32,
     dither_algorithm: String,
+    /// BlurHash placeholder string, shown while `thumb_ready` is false.
+    blurhash: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -93,6 +115,7 @@ struct TemplateContext {
 struct ThumbStatus {
     ready: bool,
     thumb_path: String,
+    blurhash: Option<String>,
 }
 
 /// Response for display configuration API endpoint.
@@ -110,8 +133,6 @@ struct DisplayConfig {
 /// Read display configuration from /etc/inky-soup/display.conf.
 /// Falls back to 5.7" Inky Impression defaults if file doesn't exist.
 fn get_display_config() -> DisplayConfig {
-    let config_path = "/etc/inky-soup/display.conf";
-
     // Default values for 5.7" Inky Impression.
     let mut config = DisplayConfig {
         width: 600,
@@ -122,55 +143,93 @@ fn get_display_config() -> DisplayConfig {
         color: "multi".to_string(),
     };
 
-    // Try to read config file.
-    if let Ok(contents) = fs::read_to_string(config_path) {
-        for line in contents.lines() {
-            let line = line.trim();
-            if line.starts_with('#') || line.is_empty() {
-                continue;
+    let pairs = config::read_config_pairs(config::DISPLAY_CONFIG_PATH);
+    if pairs.is_empty() {
+        debug!("Using default display config ({}x{})", config.width, config.height);
+        return config;
+    }
+
+    for (key, value) in &pairs {
+        match key.as_str() {
+            "DISPLAY_WIDTH" => {
+                if let Ok(v) = value.parse() {
+                    config.width = v;
+                }
+            }
+            "DISPLAY_HEIGHT" => {
+                if let Ok(v) = value.parse() {
+                    config.height = v;
+                }
+            }
+            "THUMB_WIDTH" => {
+                if let Ok(v) = value.parse() {
+                    config.thumb_width = v;
+                }
+            }
+            "THUMB_HEIGHT" => {
+                if let Ok(v) = value.parse() {
+                    config.thumb_height = v;
+                }
             }
+            "DISPLAY_MODEL" => {
+                config.model = value.clone();
+            }
+            "DISPLAY_COLOR" => {
+                config.color = value.clone();
+            }
+            _ => {}
+        }
+    }
+    debug!(
+        "Loaded display config from {}: {}x{}",
+        config::DISPLAY_CONFIG_PATH,
+        config.width,
+        config.height
+    );
 
-            if let Some((key, value)) = line.split_once('=') {
-                let key = key.trim();
-                let value = value.trim();
+    config
+}
 
-                match key {
-                    "DISPLAY_WIDTH" => {
-                        if let Ok(v) = value.parse() {
-                            config.width = v;
-                        }
-                    }
-                    "DISPLAY_HEIGHT" => {
-                        if let Ok(v) = value.parse() {
-                            config.height = v;
-                        }
-                    }
-                    "THUMB_WIDTH" => {
-                        if let Ok(v) = value.parse() {
-                            config.thumb_width = v;
-                        }
-                    }
-                    "THUMB_HEIGHT" => {
-                        if let Ok(v) = value.parse() {
-                            config.thumb_height = v;
-                        }
-                    }
-                    "DISPLAY_MODEL" => {
-                        config.model = value.to_string();
-                    }
-                    "DISPLAY_COLOR" => {
-                        config.color = value.to_string();
-                    }
-                    _ => {}
+/// Read upload validation limits from the same /etc/inky-soup/display.conf
+/// used by `get_display_config`. Falls back to generous-but-bounded defaults
+/// (20 MB, 8000x8000, 40 megapixels) if the file doesn't exist or a key is
+/// absent, which is enough headroom for any real e-ink upload while still
+/// refusing decompression-bomb-sized files.
+fn get_upload_limits() -> validate::UploadLimits {
+    let mut limits = validate::UploadLimits {
+        max_file_size: 20 * 1024 * 1024,
+        max_width: 8000,
+        max_height: 8000,
+        max_area: 40_000_000,
+    };
+
+    for (key, value) in config::read_config_pairs(config::DISPLAY_CONFIG_PATH) {
+        match key.as_str() {
+            "MAX_FILE_SIZE" => {
+                if let Ok(v) = value.parse() {
+                    limits.max_file_size = v;
+                }
+            }
+            "MAX_WIDTH" => {
+                if let Ok(v) = value.parse() {
+                    limits.max_width = v;
+                }
+            }
+            "MAX_HEIGHT" => {
+                if let Ok(v) = value.parse() {
+                    limits.max_height = v;
                 }
             }
+            "MAX_AREA" => {
+                if let Ok(v) = value.parse() {
+                    limits.max_area = v;
+                }
+            }
+            _ => {}
         }
-        debug!("Loaded display config from {}: {}x{}", config_path, config.width, config.height);
-    } else {
-        debug!("Using default display config ({}x{})", config.width, config.height);
     }
 
-    config
+    limits
 }
 
 /// Sanitizes a filename to prevent path traversal attacks.
@@ -195,47 +254,40 @@ fn sanitize_filename(filename: &str) -> Option<String> {
     Some(name.to_string())
 }
 
-fn get_gallery_images() -> Vec<GalleryImage> {
+async fn get_gallery_images(stores: &ImageStores) -> Vec<GalleryImage> {
     let mut images: Vec<GalleryImage> = Vec::new();
 
-    for entry in glob("static/images/*").expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => {
-                // Skip directories and non-image files.
-                if path.is_dir() {
-                    continue;
-                }
-
-                let filename = path.file_name()
-                    .and_then(|f| f.to_str())
-                    .unwrap_or("unknown")
-                    .to_string();
-
-                // Skip metadata files (legacy and backup).
-                if filename.starts_with("metadata.json") {
-                    continue;
-                }
+    let filenames = match stores.originals.list().await {
+        Ok(filenames) => filenames,
+        Err(e) => {
+            warn!("Error listing gallery images: {}", e);
+            Vec::new()
+        }
+    };
 
-                let image_path = format!("images/{}", filename);
-                let thumb_path = format!("static/images/thumbs/{}.png", filename);
-                let thumb_ready = Path::new(&thumb_path).exists();
-
-                // Load all metadata for this image.
-                let meta = metadata::get_all_metadata(&filename);
-
-                images.push(GalleryImage {
-                    path: image_path,
-                    filename,
-                    thumb_ready,
-                    filter: meta.filter,
-                    saturation: meta.saturation,
-                    brightness: meta.brightness,
-                    contrast: meta.contrast,
-                    dither_algorithm: meta.dither_algorithm,
-                });
-            },
-            Err(e) => warn!("Error reading gallery entry: {:?}", e),
+    for filename in filenames {
+        // Skip metadata files (legacy and backup).
+        if filename.starts_with("metadata.json") {
+            continue;
         }
+
+        let image_path = config::original_asset(&filename).url;
+        let thumb_ready = stores.thumbs.exists(&format!("{}.png", filename)).await;
+
+        // Load all metadata for this image.
+        let meta = metadata::get_all_metadata(&filename);
+
+        images.push(GalleryImage {
+            path: image_path,
+            filename,
+            thumb_ready,
+            filter: meta.filter,
+            saturation: meta.saturation,
+            brightness: meta.brightness,
+            contrast: meta.contrast,
+            dither_algorithm: meta.dither_algorithm,
+            blurhash: meta.blurhash,
+        });
     }
 
     debug!("Found {} images in gallery", images.len());
@@ -251,16 +303,29 @@ fn display_config() -> Json<DisplayConfig> {
 
 /// API endpoint to check if a gallery thumbnail exists.
 #[get("/api/thumb-status/<filename>")]
-fn thumb_status(filename: &str) -> Json<ThumbStatus> {
-    let thumb_path = format!("static/images/thumbs/{}.png", filename);
-    let ready = Path::new(&thumb_path).exists();
+async fn thumb_status(filename: &str, stores: &State<ImageStores>) -> Json<ThumbStatus> {
+    let ready = stores.thumbs.exists(&format!("{}.png", filename)).await;
 
     Json(ThumbStatus {
         ready,
-        thumb_path: format!("images/thumbs/{}.png", filename),
+        thumb_path: config::thumb_asset(filename).url,
+        blurhash: metadata::get_all_metadata(filename).blurhash,
     })
 }
 
+/// Serves originals/cache/dithered/thumbs with `Last-Modified`,
+/// `Cache-Control`, and (for originals) `Range` support, taking over from
+/// `FileServer` for this one subtree. See `image_serving` for the logic.
+#[get("/images/<path..>")]
+async fn serve_image(
+    path: PathBuf,
+    if_modified_since: image_serving::IfModifiedSince,
+    range: image_serving::RangeHeader,
+    stores: &State<ImageStores>,
+) -> Result<Response<'static>, Status> {
+    image_serving::respond(path, if_modified_since, range, stores).await
+}
+
 /// Request to lock or refresh a lock on an image.
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
@@ -350,6 +415,13 @@ struct UploadResponse {
     success: bool,
     message: String,
     filename: Option<String>,
+    /// True if the upload's decoded content matched an existing image and
+    /// `filename` refers to that existing entry rather than a new file.
+    deduplicated: bool,
+    /// Format sniffed from the upload's magic bytes, e.g. "png".
+    format: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
 }
 
 /// Response for upload-dithered endpoint.
@@ -412,6 +484,30 @@ struct UploadThumbResponse {
     path: Option<String>,
 }
 
+/// Form data for a server-side dithered render request: the same
+/// filter/saturation/brightness/contrast/dither_algorithm parameters
+/// `/api/upload-dithered` receives, minus the file, since the original
+/// gallery image is read from disk instead.
+#[derive(Debug, FromForm)]
+struct RenderRequest {
+    filename: String,
+    filter: String,
+    saturation: f32,
+    brightness: i32,
+    contrast: i32,
+    dither_algorithm: String,
+    session_id: String,
+}
+
+/// Response for the /api/render endpoint.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct RenderResponse {
+    success: bool,
+    message: String,
+    path: Option<String>,
+}
+
 /// Response for flash submission (queue).
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
@@ -422,6 +518,13 @@ struct FlashResponse {
     queue_position: usize,
 }
 
+/// Response for enqueuing a background job.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct JobEnqueuedResponse {
+    job_id: u64,
+}
+
 /// Simplified job info for queue display.
 #[derive(Serialize)]
 #[serde(crate = "rocket::serde")]
@@ -446,6 +549,7 @@ struct FlashStatusResponse {
 async fn upload_dithered(
     mut form: Form<DitheredUpload<'_>>,
     locks_state: &State<ImageLocksState>,
+    stores: &State<ImageStores>,
 ) -> Json<UploadDitheredResponse> {
     // Sanitize filename to prevent path traversal.
     let filename = match sanitize_filename(&form.filename) {
@@ -485,30 +589,55 @@ async fn upload_dithered(
         });
     }
 
-    // Save dithered image to dithered directory (always as PNG).
-    let dithered_path = format!("static/images/dithered/{}.png", filename);
+    if let Err(e) = validate::validate_upload(&form.file, get_upload_limits()).await {
+        warn!("Rejected dithered upload for {}: {}", filename, e);
+        return Json(UploadDitheredResponse {
+            success: false,
+            message: format!("Invalid image: {}", e),
+            path: None,
+        });
+    }
 
-    match form.file.copy_to(&dithered_path).await {
+    // Save dithered image to the dithered store (always as PNG).
+    let dithered_key = format!("{}.png", filename);
+
+    let bytes = match validate::read_temp_file(&form.file).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read dithered upload {}: {}", filename, e);
+            return Json(UploadDitheredResponse {
+                success: false,
+                message: format!("Failed to save dithered image: {}", e),
+                path: None,
+            });
+        }
+    };
+
+    match stores.dithered.put(&dithered_key, bytes).await {
         Ok(()) => {
+            derived_manifest::register(&filename, derived_manifest::DerivedTree::Dithered, &dithered_key);
+
             // Store all settings in metadata.
             info!(
                 "Saving metadata for {}: filter={}, sat={}, bright={}, contrast={}, dither={}",
                 filename, filter, saturation, brightness, contrast, dither_algorithm
             );
-            metadata::save_all_settings(
+            if let Err(e) = metadata::save_all_settings(
                 &filename,
                 &filter,
                 saturation,
                 brightness,
                 contrast,
                 &dither_algorithm,
-            );
+            ) {
+                error!("Failed to save metadata for {}: {}", filename, e);
+            }
             debug!("Saved dithered image: {}", filename);
 
             Json(UploadDitheredResponse {
                 success: true,
                 message: "Dithered image uploaded successfully".to_string(),
-                path: Some(format!("images/dithered/{}.png", filename)),
+                path: Some(config::dithered_asset(&filename).url),
             })
         }
         Err(e) => {
@@ -527,6 +656,7 @@ async fn upload_dithered(
 async fn upload_cache(
     mut form: Form<CacheUpload<'_>>,
     locks_state: &State<ImageLocksState>,
+    stores: &State<ImageStores>,
 ) -> Json<UploadCacheResponse> {
     // Sanitize filename to prevent path traversal.
     let filename = match sanitize_filename(&form.filename) {
@@ -570,11 +700,34 @@ async fn upload_cache(
         }
     }
 
-    // Save cache image to cache directory.
-    let cache_path = format!("static/images/cache/{}.png", filename);
+    if let Err(e) = validate::validate_upload(&form.file, get_upload_limits()).await {
+        warn!("Rejected cache upload for {}: {}", filename, e);
+        return Json(UploadCacheResponse {
+            success: false,
+            message: format!("Invalid image: {}", e),
+            path: None,
+        });
+    }
+
+    // Save cache image to the cache store.
+    let cache_key = format!("{}.png", filename);
+
+    let bytes = match validate::read_temp_file(&form.file).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read cache upload {}: {}", filename, e);
+            return Json(UploadCacheResponse {
+                success: false,
+                message: format!("Failed to save cache image: {}", e),
+                path: None,
+            });
+        }
+    };
 
-    match form.file.copy_to(&cache_path).await {
+    match stores.cache.put(&cache_key, bytes).await {
         Ok(()) => {
+            derived_manifest::register(&filename, derived_manifest::DerivedTree::Cache, &cache_key);
+
             // Save settings if any are provided.
             if filter.is_some() || saturation.is_some() || brightness.is_some()
                 || contrast.is_some() || dither_algorithm.is_some() {
@@ -593,19 +746,21 @@ async fn upload_cache(
                     filename, final_filter, final_saturation, final_brightness, final_contrast, final_dither
                 );
 
-                metadata::save_all_settings(
+                if let Err(e) = metadata::save_all_settings(
                     &filename,
                     final_filter,
                     final_saturation,
                     final_brightness,
                     final_contrast,
                     final_dither,
-                );
+                ) {
+                    error!("Failed to save metadata for {}: {}", filename, e);
+                }
 
                 // Remove dithered file if it exists since cache changed.
-                let dithered_path = format!("static/images/dithered/{}.png", filename);
-                if Path::new(&dithered_path).exists() {
-                    let _ = fs::remove_file(&dithered_path);
+                let dithered_key = format!("{}.png", filename);
+                if stores.dithered.exists(&dithered_key).await {
+                    let _ = stores.dithered.delete(&dithered_key).await;
                     debug!("Removed dithered cache: {}", filename);
                 }
             }
@@ -614,7 +769,7 @@ async fn upload_cache(
             Json(UploadCacheResponse {
                 success: true,
                 message: "Cache image uploaded successfully".to_string(),
-                path: Some(format!("images/cache/{}.png", filename)),
+                path: Some(config::cache_asset(&filename).url),
             })
         }
         Err(e) => {
@@ -630,7 +785,7 @@ async fn upload_cache(
 
 /// API endpoint to upload a gallery thumbnail.
 #[post("/api/upload-thumb", data = "<form>")]
-async fn upload_thumb(mut form: Form<ThumbUpload<'_>>) -> Json<UploadThumbResponse> {
+async fn upload_thumb(mut form: Form<ThumbUpload<'_>>, stores: &State<ImageStores>) -> Json<UploadThumbResponse> {
     // Sanitize filename to prevent path traversal.
     let filename = match sanitize_filename(&form.filename) {
         Some(name) => name,
@@ -643,18 +798,40 @@ async fn upload_thumb(mut form: Form<ThumbUpload<'_>>) -> Json<UploadThumbRespon
             });
         }
     };
+    if let Err(e) = validate::validate_upload(&form.file, get_upload_limits()).await {
+        warn!("Rejected thumbnail upload for {}: {}", filename, e);
+        return Json(UploadThumbResponse {
+            success: false,
+            message: format!("Invalid image: {}", e),
+            path: None,
+        });
+    }
+
     debug!("Saving gallery thumbnail: {}", filename);
 
-    // Save thumbnail to thumbs directory.
-    let thumb_path = format!("static/images/thumbs/{}.png", filename);
+    // Save thumbnail to the thumbs store.
+    let thumb_key = format!("{}.png", filename);
 
-    match form.file.copy_to(&thumb_path).await {
+    let bytes = match validate::read_temp_file(&form.file).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read thumbnail upload {}: {}", filename, e);
+            return Json(UploadThumbResponse {
+                success: false,
+                message: format!("Failed to save thumbnail: {}", e),
+                path: None,
+            });
+        }
+    };
+
+    match stores.thumbs.put(&thumb_key, bytes).await {
         Ok(()) => {
+            derived_manifest::register(&filename, derived_manifest::DerivedTree::Thumbs, &thumb_key);
             debug!("Saved gallery thumbnail: {}", filename);
             Json(UploadThumbResponse {
                 success: true,
                 message: "Thumbnail uploaded successfully".to_string(),
-                path: Some(format!("images/thumbs/{}.png", filename)),
+                path: Some(config::thumb_asset(&filename).url),
             })
         }
         Err(e) => {
@@ -668,19 +845,159 @@ async fn upload_thumb(mut form: Form<ThumbUpload<'_>>) -> Json<UploadThumbRespon
     }
 }
 
+/// API endpoint to render a dithered display image server-side from the
+/// original gallery image, so thin clients (and scripted batch jobs) don't
+/// need a canvas to produce what `/api/upload-dithered` expects.
+#[post("/api/render", data = "<form>")]
+async fn render_dithered(
+    form: Form<RenderRequest>,
+    locks_state: &State<ImageLocksState>,
+    stores: &State<ImageStores>,
+) -> Json<RenderResponse> {
+    let filename = match sanitize_filename(&form.filename) {
+        Some(name) => name,
+        None => {
+            warn!("Rejected render request for invalid filename: {}", form.filename);
+            return Json(RenderResponse {
+                success: false,
+                message: "Invalid filename".to_string(),
+                path: None,
+            });
+        }
+    };
+
+    let has_lock = image_locks::verify_lock_ownership(locks_state, &filename, &form.session_id)
+        .await
+        .unwrap_or(false);
+
+    if !has_lock {
+        warn!("Render denied for {}: session {} does not own lock", filename, form.session_id);
+        return Json(RenderResponse {
+            success: false,
+            message: "You do not have edit access to this image".to_string(),
+            path: None,
+        });
+    }
+
+    info!(
+        "Render started: {} (filter: {}, sat: {}, bright: {}, contrast: {}, dither: {})",
+        filename, form.filter, form.saturation, form.brightness, form.contrast, form.dither_algorithm
+    );
+
+    let dithered_key = format!("{}.png", filename);
+    let dithered_path = format!("static/images/dithered/{}.png", filename);
+    let filter = form.filter.clone();
+    let saturation = form.saturation;
+    let brightness = form.brightness;
+    let contrast = form.contrast;
+    let dither_algorithm = form.dither_algorithm.clone();
+    let color_mode = get_display_config().color;
+
+    // Read the original through `stores` rather than a local path, so this
+    // works the same whether originals live on the SD card or in S3 -
+    // matching `dither::ensure_dithered`'s pipeline, which this mirrors.
+    let original_bytes = match stores.originals.get(&filename).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Render failed to read original {}: {}", filename, e);
+            return Json(RenderResponse {
+                success: false,
+                message: format!("Render failed: {}", e),
+                path: None,
+            });
+        }
+    };
+
+    let result = tokio::task::spawn_blocking(move || {
+        let img = cache_worker::open_oriented_bytes(&original_bytes)?;
+        let resize_filter = metadata::parse_filter(&filter);
+        let resized = img
+            .resize_exact(cache_worker::DISPLAY_WIDTH, cache_worker::DISPLAY_HEIGHT, resize_filter)
+            .to_rgb8();
+
+        let palette = processor::palette_for(&color_mode);
+        let rendered = processor::render(&resized, palette, saturation, brightness, contrast, &dither_algorithm);
+
+        let mut bytes = Vec::new();
+        rendered
+            .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .map_err(|e| format!("Failed to encode rendered image: {}", e))?;
+        Ok(bytes)
+    })
+    .await;
+
+    match result {
+        Ok(Ok(png_bytes)) => {
+            if let Err(e) = stores.dithered.put(&dithered_key, png_bytes.clone()).await {
+                error!("Render failed to save dithered image for {}: {}", filename, e);
+                return Json(RenderResponse {
+                    success: false,
+                    message: format!("Render failed: {}", e),
+                    path: None,
+                });
+            }
+            derived_manifest::register(&filename, derived_manifest::DerivedTree::Dithered, &dithered_key);
+
+            // Also keep a local copy for the flasher subprocess, which needs
+            // a filesystem path to read regardless of backend.
+            if let Err(e) = tokio::fs::write(&dithered_path, &png_bytes).await {
+                error!("Failed to cache rendered image locally for {}: {}", filename, e);
+            }
+
+            info!("Render completed: {}", filename);
+
+            if let Err(e) = metadata::save_all_settings(
+                &filename,
+                &form.filter,
+                saturation,
+                brightness,
+                contrast,
+                &form.dither_algorithm,
+            ) {
+                error!("Failed to save metadata for {}: {}", filename, e);
+            }
+
+            Json(RenderResponse {
+                success: true,
+                message: "Image rendered successfully".to_string(),
+                path: Some(config::dithered_asset(&filename).url),
+            })
+        }
+        Ok(Err(e)) => {
+            error!("Render failed for {}: {}", filename, e);
+            Json(RenderResponse {
+                success: false,
+                message: format!("Render failed: {}", e),
+                path: None,
+            })
+        }
+        Err(e) => {
+            error!("Render task panicked for {}: {}", filename, e);
+            Json(RenderResponse {
+                success: false,
+                message: "Render task panicked".to_string(),
+                path: None,
+            })
+        }
+    }
+}
+
 #[get("/")]
-fn upload_form() -> Template {
+async fn upload_form(stores: &State<ImageStores>) -> Template {
     debug!("Rendering gallery page");
 
     Template::render("index", &TemplateContext {
-        images: get_gallery_images(),
+        images: get_gallery_images(stores).await,
         values: vec!["Upload images, then select them from the Gallery to Flash to the screen.".to_string()],
         errors: vec![],
     })
 }
 
 #[post("/delete", data = "<form>")]
-async fn submit_delete_image<'r>(mut form: Form<Contextual<'r, SubmitDeleteImage>>) -> Result<Redirect, (Status, String)> {
+async fn submit_delete_image<'r>(
+    mut form: Form<Contextual<'r, SubmitDeleteImage>>,
+    stores: &State<ImageStores>,
+) -> Result<Redirect, (Status, String)> {
     let submission = match form.value {
         Some(ref mut s) => s,
         None => {
@@ -694,42 +1011,43 @@ async fn submit_delete_image<'r>(mut form: Form<Contextual<'r, SubmitDeleteImage
     let filename = Path::new(&image_file)
         .file_name()
         .and_then(|f| f.to_str())
-        .unwrap_or("unknown");
+        .unwrap_or("unknown")
+        .to_string();
 
     info!("Delete started: {}", filename);
 
     // Delete original file first - this is the critical operation.
-    if let Err(e) = fs::remove_file(&image_file) {
+    if let Err(e) = stores.originals.delete(&filename).await {
         error!("Delete failed for {}: {}", filename, e);
         return Err((Status::InternalServerError, format!("Failed to delete image: {}", e)));
     }
 
     // Also delete cached version if it exists (non-fatal if this fails).
-    let cache_path = cache_worker::get_cache_path(&image_file);
-    if Path::new(&cache_path).exists() {
-        if let Err(e) = fs::remove_file(&cache_path) {
+    let cache_key = format!("{}.png", filename);
+    if stores.cache.exists(&cache_key).await {
+        if let Err(e) = stores.cache.delete(&cache_key).await {
             warn!("Failed to remove cached image for {}: {}", filename, e);
         }
     }
 
     // Also delete gallery thumbnail if it exists (non-fatal if this fails).
-    let thumb_path = format!("static/images/thumbs/{}.png", filename);
-    if Path::new(&thumb_path).exists() {
-        if let Err(e) = fs::remove_file(&thumb_path) {
+    let thumb_key = format!("{}.png", filename);
+    if stores.thumbs.exists(&thumb_key).await {
+        if let Err(e) = stores.thumbs.delete(&thumb_key).await {
             warn!("Failed to remove thumbnail for {}: {}", filename, e);
         }
     }
 
     // Also delete dithered version if it exists (non-fatal if this fails).
-    let dithered_path = format!("static/images/dithered/{}.png", filename);
-    if Path::new(&dithered_path).exists() {
-        if let Err(e) = fs::remove_file(&dithered_path) {
+    let dithered_key = format!("{}.png", filename);
+    if stores.dithered.exists(&dithered_key).await {
+        if let Err(e) = stores.dithered.delete(&dithered_key).await {
             warn!("Failed to remove dithered image for {}: {}", filename, e);
         }
     }
 
     // Clean up metadata.
-    metadata::delete_metadata(filename);
+    metadata::delete_metadata(&filename);
 
     info!("Delete completed: {}", filename);
     Ok(Redirect::to(uri!(upload_form)))
@@ -754,6 +1072,7 @@ async fn submit_flash_image<'r>(
     let filename = &submission.submission.filename;
     let dithered_path = format!("static/{}", submission.submission.image_file_path.clone());
     let flash_twice = submission.submission.flash_twice;
+    let force_dither = submission.submission.force_dither;
     let session_id = &submission.submission.session_id;
 
     info!("Flash request received: {} (flash_twice: {}, session: {})", filename, flash_twice, session_id);
@@ -768,15 +1087,21 @@ async fn submit_flash_image<'r>(
         return Err((Status::Forbidden, "You do not have edit access to this image".to_string()));
     }
 
-    // Require pre-dithered version to exist (uploaded from preview dialog).
-    if !Path::new(&dithered_path).exists() {
-        error!("Flash failed for {}: pre-dithered image not found", filename);
-        return Err((Status::NotFound, format!("Pre-dithered image not found: {}", filename)));
-    }
+    // No pre-dithered version is required here: the flash worker renders
+    // one server-side (see `dither::ensure_dithered`) if it's missing or
+    // `force_dither` is set.
+
+    // The dithered image's pixels are already EXIF-normalized to upright by
+    // `cache_worker::open_oriented`/`open_oriented_bytes` before rendering,
+    // so there's no per-image EXIF rotation left to apply here - only the
+    // physical mount's counter-rotation, which `compute_effective_rotation`
+    // reduces to when given an identity EXIF orientation.
+    let mount_rotation_degrees = display_settings::load_rotation_degrees();
+    let (rotation_degrees, _flip) = display_settings::compute_effective_rotation(1, mount_rotation_degrees);
 
     // Add to queue.
     let mut queue = queue_state.lock().await;
-    let job_id = queue.enqueue(filename.to_string(), dithered_path, flash_twice);
+    let job_id = queue.enqueue(filename.to_string(), dithered_path, flash_twice, rotation_degrees, None, force_dither);
     let queue_position = queue.get_position(job_id).unwrap_or(0);
     drop(queue);
 
@@ -834,10 +1159,115 @@ async fn flash_job_status(job_id: u64, queue_state: &State<FlashQueueState>) ->
     Err(Status::NotFound)
 }
 
+/// Streams flash queue events over SSE, replacing the need to poll
+/// `/api/flash/status` in a loop to watch a long double-flash. Emits the
+/// current snapshot first so a client connecting mid-queue is consistent
+/// with one that's been subscribed since the queue was empty.
+#[get("/api/flash/events")]
+async fn flash_events(queue_state: &State<FlashQueueState>) -> EventStream![] {
+    let (snapshot, mut events) = {
+        let queue = queue_state.lock().await;
+        (queue.events_snapshot(), queue.subscribe_events())
+    };
+
+    EventStream! {
+        for event in snapshot {
+            if let Ok(json) = serde_json::to_string(&event) {
+                yield Event::data(json);
+            }
+        }
+
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Ok(json) = serde_json::to_string(&event) {
+                        yield Event::data(json);
+                    }
+                }
+                // A slow subscriber missed some events; keep going with
+                // whatever arrives next rather than dropping the connection.
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// Like `flash_events`, but filtered to a single job id, for clients that
+/// only care about the flash they just queued.
+#[get("/api/flash/events/<job_id>")]
+async fn flash_job_events(job_id: u64, queue_state: &State<FlashQueueState>) -> EventStream![] {
+    let (snapshot, mut events) = {
+        let queue = queue_state.lock().await;
+        (queue.events_snapshot_for(job_id), queue.subscribe_events())
+    };
+
+    EventStream! {
+        if let Some(event) = snapshot {
+            if let Ok(json) = serde_json::to_string(&event) {
+                yield Event::data(json);
+            }
+        }
+
+        loop {
+            match events.recv().await {
+                Ok(event) if event_job_id(&event) == Some(job_id) => {
+                    if let Ok(json) = serde_json::to_string(&event) {
+                        yield Event::data(json);
+                    }
+                }
+                Ok(_) => continue,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
+
+/// The job id a `FlashEvent` is about, for filtering `flash_job_events`'s
+/// stream down to one job.
+fn event_job_id(event: &FlashEvent) -> Option<u64> {
+    match event {
+        FlashEvent::Enqueued { job }
+        | FlashEvent::Started { job }
+        | FlashEvent::Retrying { job }
+        | FlashEvent::Completed { job }
+        | FlashEvent::Failed { job } => Some(job.job_id),
+        FlashEvent::Phase { job_id, .. } => Some(*job_id),
+    }
+}
+
+/// Enqueues a background job that re-renders the cache image for every
+/// original image from its saved `ImageMetadata`. Runs in the background;
+/// poll `/api/jobs/<job_id>` for progress.
+#[post("/api/jobs/rebuild-derived-assets")]
+async fn submit_rebuild_derived_assets_job(jobs_state: &State<JobsState>) -> Json<JobEnqueuedResponse> {
+    let job_id = jobs::enqueue_rebuild_derived_assets_job(jobs_state).await;
+    info!("Rebuild derived assets job {} queued", job_id);
+    Json(JobEnqueuedResponse { job_id })
+}
+
+/// Gets the persisted progress report for a background job.
+#[get("/api/jobs/<job_id>")]
+fn job_status(job_id: u64) -> Result<Json<JobReport>, Status> {
+    jobs::get_job_report(job_id).map(Json).ok_or(Status::NotFound)
+}
+
+/// Requests cancellation of a running background job.
+#[post("/api/jobs/<job_id>/cancel")]
+async fn cancel_job(job_id: u64, jobs_state: &State<JobsState>) -> Status {
+    if jobs::cancel_job(jobs_state, job_id).await {
+        Status::Ok
+    } else {
+        Status::NotFound
+    }
+}
+
 #[post("/upload", data = "<form>")]
 async fn submit_new_image<'r>(
-    mut form: Form<Contextual<'r, SubmitNewImage<'r>>>
-) -> Json<UploadResponse> {
+    mut form: Form<Contextual<'r, SubmitNewImage<'r>>>,
+    stores: &State<ImageStores>,
+) -> (Status, Json<UploadResponse>) {
     match form.value {
         Some(ref mut submission) => {
             let file = &mut submission.submission.file;
@@ -853,37 +1283,305 @@ async fn submit_new_image<'r>(
 
             info!("Upload started: {}", filename);
 
-            // Save as new image in gallery.
-            let image_file_path = format!("static/images/{}", filename);
-            match file.copy_to(image_file_path.clone()).await {
-                Ok(_) => {
-                    info!("Upload completed: {}", filename);
-                    // Cache is now generated client-side and uploaded separately via /api/upload-cache.
+            let image_info = match validate::validate_upload(file, get_upload_limits()).await {
+                Ok(info) => info,
+                Err(e) => {
+                    warn!("Rejected upload for {}: {}", filename, e);
+                    return (
+                        Status::UnsupportedMediaType,
+                        Json(UploadResponse {
+                            success: false,
+                            message: format!("Invalid image: {}", e),
+                            filename: None,
+                            deduplicated: false,
+                            format: None,
+                            width: None,
+                            height: None,
+                        }),
+                    );
+                }
+            };
+
+            // Stage the upload in its own directory (skipped by the gallery's
+            // `static/images/*` glob since it's a directory entry itself) so
+            // its content hash can be checked against the gallery before
+            // it's committed as a new entry (or discarded as a dup).
+            if let Err(e) = fs::create_dir_all("static/images/.staging") {
+                error!("Failed to create upload staging directory: {}", e);
+            }
+            let staging_path = format!("static/images/.staging/{}-{}.tmp", std::process::id(), filename);
+            let upload_hash = match ingest::stage_and_hash(file, &staging_path).await {
+                Ok(hash) => hash,
+                Err(e) => {
+                    error!("Upload failed for {}: {}", filename, e);
+                    return (
+                        Status::InternalServerError,
+                        Json(UploadResponse {
+                            success: false,
+                            message: format!("Upload failed: {}", e),
+                            filename: None,
+                            deduplicated: false,
+                            format: None,
+                            width: None,
+                            height: None,
+                        }),
+                    );
+                }
+            };
+
+            // A byte-identical re-upload is caught here, before paying for
+            // a decode at all.
+            if let Some(existing) = metadata::find_by_upload_hash(&upload_hash) {
+                let _ = fs::remove_file(&staging_path);
+                info!("Upload deduplicated (byte-identical): {} matches existing {}", filename, existing);
+                return (
+                    Status::Ok,
                     Json(UploadResponse {
                         success: true,
-                        message: "Upload completed successfully".to_string(),
-                        filename: Some(filename),
-                    })
+                        message: "Duplicate of an existing image; reusing it instead of storing a copy".to_string(),
+                        filename: Some(existing),
+                        deduplicated: true,
+                        format: Some(validate::format_name(image_info.format).to_string()),
+                        width: Some(image_info.width),
+                        height: Some(image_info.height),
+                    }),
+                );
+            }
+
+            let hash_path = staging_path.clone();
+            let hash_result = tokio::task::spawn_blocking(move || {
+                let img = cache_worker::open_oriented(Path::new(&hash_path))?;
+                let content_hash = processor::content_hash(&img);
+                let sample = img.resize(32, 32, image::imageops::FilterType::Triangle).to_rgb8();
+                let blurhash = blurhash::encode(&sample, 4, 3);
+                let perceptual_hash = phash::compute(&img);
+                Ok((content_hash, blurhash, perceptual_hash))
+            })
+            .await;
+
+            let (content_hash, blurhash, perceptual_hash) = match hash_result {
+                Ok(Ok(result)) => result,
+                Ok(Err(e)) => {
+                    let _ = fs::remove_file(&staging_path);
+                    error!("Failed to process upload {}: {}", filename, e);
+                    return (
+                        Status::InternalServerError,
+                        Json(UploadResponse {
+                            success: false,
+                            message: format!("Failed to process upload: {}", e),
+                            filename: None,
+                            deduplicated: false,
+                            format: None,
+                            width: None,
+                            height: None,
+                        }),
+                    );
+                }
+                Err(e) => {
+                    let _ = fs::remove_file(&staging_path);
+                    error!("Upload processing task panicked for {}: {}", filename, e);
+                    return (
+                        Status::InternalServerError,
+                        Json(UploadResponse {
+                            success: false,
+                            message: "Upload processing panicked".to_string(),
+                            filename: None,
+                            deduplicated: false,
+                            format: None,
+                            width: None,
+                            height: None,
+                        }),
+                    );
                 }
+            };
+
+            if let Some(existing) = metadata::find_by_content_hash(&content_hash) {
+                let _ = fs::remove_file(&staging_path);
+                info!("Upload deduplicated: {} matches existing {}", filename, existing);
+                return (
+                    Status::Ok,
+                    Json(UploadResponse {
+                        success: true,
+                        message: "Duplicate of an existing image; reusing it instead of storing a copy".to_string(),
+                        filename: Some(existing),
+                        deduplicated: true,
+                        format: Some(validate::format_name(image_info.format).to_string()),
+                        width: Some(image_info.width),
+                        height: Some(image_info.height),
+                    }),
+                );
+            }
+
+            // No existing match: commit the staged upload as a new gallery entry.
+            let staged_bytes = match tokio::fs::read(&staging_path).await {
+                Ok(bytes) => bytes,
                 Err(e) => {
+                    let _ = fs::remove_file(&staging_path);
                     error!("Upload failed for {}: {}", filename, e);
+                    return (
+                        Status::InternalServerError,
+                        Json(UploadResponse {
+                            success: false,
+                            message: format!("Upload failed: {}", e),
+                            filename: None,
+                            deduplicated: false,
+                            format: None,
+                            width: None,
+                            height: None,
+                        }),
+                    );
+                }
+            };
+
+            if let Err(e) = stores.originals.put(&filename, staged_bytes).await {
+                let _ = fs::remove_file(&staging_path);
+                error!("Upload failed for {}: {}", filename, e);
+                return (
+                    Status::InternalServerError,
                     Json(UploadResponse {
                         success: false,
                         message: format!("Upload failed: {}", e),
                         filename: None,
-                    })
-                }
+                        deduplicated: false,
+                        format: None,
+                        width: None,
+                        height: None,
+                    }),
+                );
+            }
+            let _ = fs::remove_file(&staging_path);
+
+            if let Err(e) = metadata::save_content_hash(&filename, &content_hash) {
+                error!("Failed to save content hash for {}: {}", filename, e);
+            }
+            if let Err(e) = metadata::save_upload_hash(&filename, &upload_hash) {
+                error!("Failed to save upload hash for {}: {}", filename, e);
+            }
+            if let Err(e) = metadata::save_blurhash(&filename, &blurhash) {
+                error!("Failed to save blurhash for {}: {}", filename, e);
+            }
+            if let Err(e) = metadata::save_perceptual_hash(&filename, perceptual_hash) {
+                error!("Failed to save perceptual hash for {}: {}", filename, e);
             }
+
+            info!("Upload completed: {}", filename);
+            // Cache is now generated client-side and uploaded separately via /api/upload-cache.
+            (
+                Status::Ok,
+                Json(UploadResponse {
+                    success: true,
+                    message: "Upload completed successfully".to_string(),
+                    filename: Some(filename),
+                    deduplicated: false,
+                    format: Some(validate::format_name(image_info.format).to_string()),
+                    width: Some(image_info.width),
+                    height: Some(image_info.height),
+                }),
+            )
         }
         None => {
             warn!("Upload form validation failed");
-            Json(UploadResponse {
-                success: false,
-                message: "Invalid form submission".to_string(),
-                filename: None,
-            })
+            (
+                Status::BadRequest,
+                Json(UploadResponse {
+                    success: false,
+                    message: "Invalid form submission".to_string(),
+                    filename: None,
+                    deduplicated: false,
+                    format: None,
+                    width: None,
+                    height: None,
+                }),
+            )
+        }
+    }
+}
+
+/// Like `submit_new_image`, but returns immediately with a job id instead of
+/// waiting for the decode/hash/commit pipeline to finish. The slow work runs
+/// on the same background-worker pattern as the flash queue; poll
+/// `/api/upload-async/<job_id>` for the result. Useful on constrained
+/// hardware where a large original can take a while to decode and hash.
+#[post("/api/upload-async", data = "<form>")]
+async fn submit_new_image_async<'r>(
+    mut form: Form<Contextual<'r, SubmitNewImage<'r>>>,
+    queue_state: &State<UploadQueueState>,
+) -> Result<Json<JobEnqueuedResponse>, (Status, String)> {
+    let submission = match form.value {
+        Some(ref mut s) => s,
+        None => {
+            warn!("Async upload form validation failed");
+            return Err((Status::BadRequest, "Invalid form submission".to_string()));
+        }
+    };
+
+    let file = &mut submission.submission.file;
+
+    // Get the full original filename including extension.
+    // TempFile::name() strips extensions, so use raw_name() instead.
+    let filename = file.raw_name()
+        .and_then(|n| sanitize_filename(n.dangerous_unsafe_unsanitized_raw().as_str()))
+        .unwrap_or_else(|| {
+            warn!("Async upload has no filename, using fallback");
+            "unnamed_upload".to_string()
+        });
+
+    info!("Async upload started: {}", filename);
+
+    if let Err(e) = validate::validate_upload(file, get_upload_limits()).await {
+        warn!("Rejected async upload for {}: {}", filename, e);
+        return Err((Status::UnsupportedMediaType, format!("Invalid image: {}", e)));
+    }
+
+    // Stage the upload the same way `submit_new_image` does, so the worker
+    // can decode/hash/commit it after this request has already returned.
+    if let Err(e) = fs::create_dir_all("static/images/.staging") {
+        error!("Failed to create upload staging directory: {}", e);
+    }
+    let staging_path = format!("static/images/.staging/{}-{}.tmp", std::process::id(), filename);
+    let upload_hash = match ingest::stage_and_hash(file, &staging_path).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("Async upload failed for {}: {}", filename, e);
+            return Err((Status::InternalServerError, format!("Upload failed: {}", e)));
+        }
+    };
+
+    let job_id = queue_state
+        .lock()
+        .await
+        .enqueue(filename.clone(), staging_path, upload_hash);
+    info!("Async upload job {} queued for {}", job_id, filename);
+
+    Ok(Json(JobEnqueuedResponse { job_id }))
+}
+
+/// Gets the status of a backgrounded upload job queued via
+/// `/api/upload-async`.
+#[get("/api/upload-async/<job_id>")]
+async fn upload_job_status(job_id: u64, queue_state: &State<UploadQueueState>) -> Result<Json<UploadJob>, Status> {
+    queue_state.lock().await.get(job_id).map(Json).ok_or(Status::NotFound)
+}
+
+/// Fairing to resume any background jobs left `Running` by an unclean shutdown.
+struct JobsFairing;
+
+#[rocket::async_trait]
+impl Fairing for JobsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Background Jobs",
+            kind: Kind::Liftoff,
         }
     }
+
+    async fn on_liftoff(&self, rocket: &Rocket<rocket::Orbit>) {
+        let jobs_state = rocket
+            .state::<JobsState>()
+            .expect("JobsState not in managed state")
+            .clone();
+        jobs::resume_interrupted_jobs(jobs_state).await;
+    }
 }
 
 /// Fairing to spawn background cleanup task.
@@ -898,9 +1596,13 @@ impl Fairing for CleanupFairing {
         }
     }
 
-    async fn on_liftoff(&self, _rocket: &Rocket<rocket::Orbit>) {
+    async fn on_liftoff(&self, rocket: &Rocket<rocket::Orbit>) {
         info!("Starting background cleanup worker (runs every 5 minutes)");
-        cleanup::spawn_cleanup_task();
+        let stores = rocket
+            .state::<ImageStores>()
+            .expect("ImageStores not in managed state")
+            .clone();
+        cleanup::spawn_cleanup_task(stores);
     }
 }
 
@@ -912,7 +1614,7 @@ impl Fairing for FlashQueueFairing {
     fn info(&self) -> Info {
         Info {
             name: "Flash Queue Worker",
-            kind: Kind::Liftoff,
+            kind: Kind::Liftoff | Kind::Shutdown,
         }
     }
 
@@ -922,7 +1624,54 @@ impl Fairing for FlashQueueFairing {
             .state::<FlashQueueState>()
             .expect("FlashQueueState not in managed state")
             .clone();
-        flash_queue::spawn_flash_worker(queue_state);
+        let worker_handle = rocket
+            .state::<FlashWorkerHandle>()
+            .expect("FlashWorkerHandle not in managed state")
+            .clone();
+        let stores = rocket
+            .state::<ImageStores>()
+            .expect("ImageStores not in managed state")
+            .clone();
+        let join_handle = flash_queue::spawn_flash_worker(queue_state, stores);
+        *worker_handle.lock().await = Some(join_handle);
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<rocket::Orbit>) {
+        info!("Flash queue worker: draining in-progress flash before shutdown...");
+        if let Some(queue_state) = rocket.state::<FlashQueueState>() {
+            queue_state.lock().await.request_shutdown();
+        }
+        if let Some(worker_handle) = rocket.state::<FlashWorkerHandle>() {
+            if let Some(join_handle) = worker_handle.lock().await.take() {
+                let _ = join_handle.await;
+            }
+        }
+    }
+}
+
+/// Fairing to spawn the background upload queue worker.
+struct UploadQueueFairing;
+
+#[rocket::async_trait]
+impl Fairing for UploadQueueFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Upload Queue Worker",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<rocket::Orbit>) {
+        info!("Starting upload queue worker");
+        let queue_state = rocket
+            .state::<UploadQueueState>()
+            .expect("UploadQueueState not in managed state")
+            .clone();
+        let stores = rocket
+            .state::<ImageStores>()
+            .expect("ImageStores not in managed state")
+            .clone();
+        upload_queue::spawn_upload_worker(queue_state, stores);
     }
 }
 
@@ -935,35 +1684,74 @@ fn rocket() -> _ {
         }
     }
 
+    // Remove any temp files a crash left behind mid-write before anything
+    // else touches the metadata directory.
+    metadata::sweep_orphaned_temp_files();
+
     // Run migration from legacy metadata format if needed.
     metadata::migrate_legacy_metadata();
 
-    // Initialize flash queue state.
-    let flash_queue_state: FlashQueueState = Arc::new(Mutex::new(FlashQueue::new()));
+    // Initialize flash queue state (persisted to disk so queued/in-flight
+    // jobs survive a restart instead of silently vanishing).
+    let flash_queue_state: FlashQueueState =
+        Arc::new(Mutex::new(FlashQueue::persistent("static/images/metadata/flash-queue.json")));
+
+    // Holds the flash worker's join handle so FlashQueueFairing::on_shutdown
+    // can await a graceful drain of any in-progress flash.
+    let flash_worker_handle: FlashWorkerHandle = Arc::new(Mutex::new(None));
+
+    // Initialize image locks state (in-process by default; see image_locks::RedisLockStore
+    // for multi-instance deployments).
+    let image_locks_state: ImageLocksState = image_locks::memory_backend();
+
+    // Holds cancellation flags for currently-running background jobs (e.g.
+    // the rebuild-derived-assets job); persisted progress lives in JobReport
+    // files under the jobs directory, so this only needs to track live runs.
+    let jobs_state: JobsState = jobs::new_jobs_state();
+
+    // Storage backend for the originals/cache/dithered/thumbs trees; see
+    // `store` module docs for the filesystem-vs-S3 tradeoffs.
+    let image_stores: ImageStores = store::build_image_stores();
 
-    // Initialize image locks state.
-    let image_locks_state: ImageLocksState = Arc::new(Mutex::new(HashMap::new()));
+    // Queue for backgrounded uploads (see `upload_queue`); lost on restart,
+    // same as the staged files it's built to process.
+    let upload_queue_state: UploadQueueState = upload_queue::new_upload_queue_state();
 
     rocket::build()
         .manage(flash_queue_state)
+        .manage(flash_worker_handle)
         .manage(image_locks_state)
+        .manage(jobs_state)
+        .manage(image_stores)
+        .manage(upload_queue_state)
         .mount("/", routes![
+            cancel_job,
             display_config,
+            flash_events,
+            flash_job_events,
             flash_job_status,
             flash_status,
+            job_status,
             lock_image,
+            render_dithered,
+            serve_image,
             submit_delete_image,
             submit_flash_image,
             submit_new_image,
+            submit_new_image_async,
+            submit_rebuild_derived_assets_job,
             thumb_status,
             unlock_image,
             upload_cache,
             upload_dithered,
             upload_form,
+            upload_job_status,
             upload_thumb
         ])
         .mount("/", FileServer::from("static"))
         .attach(Template::fairing())
         .attach(CleanupFairing)
         .attach(FlashQueueFairing)
+        .attach(JobsFairing)
+        .attach(UploadQueueFairing)
 }
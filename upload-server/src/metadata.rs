@@ -2,22 +2,40 @@
 //!
 //! Each image has its own JSON file in the metadata directory.
 //! Files are read on demand and written atomically (temp file + rename).
+//! The schema carries an explicit `version` field; older or reshaped files
+//! are brought forward through `MIGRATIONS` before being deserialized, so a
+//! schema change never silently discards a user's settings.
 
 use crate::config;
+use crate::phash;
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 const DEFAULT_FILTER: &str = "bicubic";
 const DEFAULT_SATURATION: f32 = 0.5;
 const DEFAULT_BRIGHTNESS: i32 = 0;
 const DEFAULT_CONTRAST: i32 = 0;
 const DEFAULT_DITHER_ALGORITHM: &str = "floyd-steinberg";
 
+/// Current on-disk schema version for `ImageMetadata`. Bump this and add a
+/// `migrate_vN_to_vN+1` entry to `MIGRATIONS` whenever the schema changes
+/// shape (new fields, renamed keys, different enum encodings).
+const CURRENT_VERSION: u32 = 6;
+
+fn default_version() -> u32 {
+    CURRENT_VERSION
+}
+
 /// Metadata stored for each image.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageMetadata {
+    #[serde(default = "default_version")]
+    pub version: u32,
     #[serde(default = "default_filter")]
     pub filter: String,
     #[serde(default = "default_saturation")]
@@ -28,6 +46,28 @@ pub struct ImageMetadata {
     pub contrast: i32,
     #[serde(default = "default_dither_algorithm")]
     pub dither_algorithm: String,
+    /// SHA-256 of the image's decoded pixel bytes, used to deduplicate
+    /// uploads that are the same picture under a different filename or
+    /// encoding. `None` until the upload pipeline computes it.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// SHA-256 of the originally uploaded file's raw bytes, computed while
+    /// the upload is staged to disk (see `ingest::stage_and_hash`). Catches
+    /// a byte-identical re-upload before `content_hash` would, letting the
+    /// upload pipeline skip decoding entirely. `None` until the upload
+    /// pipeline computes it.
+    #[serde(default)]
+    pub upload_hash: Option<String>,
+    /// Compact BlurHash placeholder string, computed at upload time so the
+    /// gallery can paint a blurred preview before the real thumbnail exists.
+    #[serde(default)]
+    pub blurhash: Option<String>,
+    /// 64-bit dHash perceptual fingerprint (see `phash`), used to flag
+    /// near-duplicate uploads that aren't byte- or pixel-identical (a
+    /// recompress, a resize, a minor edit). `None` until the upload
+    /// pipeline computes it.
+    #[serde(default)]
+    pub perceptual_hash: Option<u64>,
 }
 
 fn default_filter() -> String {
@@ -53,16 +93,23 @@ fn default_dither_algorithm() -> String {
 impl Default for ImageMetadata {
     fn default() -> Self {
         Self {
+            version: CURRENT_VERSION,
             filter: DEFAULT_FILTER.to_string(),
             saturation: DEFAULT_SATURATION,
             brightness: DEFAULT_BRIGHTNESS,
             contrast: DEFAULT_CONTRAST,
             dither_algorithm: DEFAULT_DITHER_ALGORITHM.to_string(),
+            content_hash: None,
+            upload_hash: None,
+            blurhash: None,
+            perceptual_hash: None,
         }
     }
 }
 
-/// Legacy metadata format for migration.
+/// Legacy metadata format for migration. This is schema "version 0": a
+/// single `static/images/metadata.json` file holding one of these per image,
+/// predating the per-file store entirely.
 #[derive(Debug, Deserialize)]
 struct LegacyImageMetadata {
     filter: String,
@@ -70,6 +117,108 @@ struct LegacyImageMetadata {
     last_dithered_saturation: Option<f32>,
 }
 
+/// Migrates a v0 (legacy single-file) entry to v1 (the original per-file
+/// shape, before the explicit `version` tag existed).
+fn migrate_v0_to_v1(value: serde_json::Value) -> serde_json::Value {
+    let legacy: LegacyImageMetadata = serde_json::from_value(value).unwrap_or(LegacyImageMetadata {
+        filter: DEFAULT_FILTER.to_string(),
+        last_dithered_saturation: None,
+    });
+
+    serde_json::json!({
+        "filter": legacy.filter,
+        "saturation": legacy.last_dithered_saturation.unwrap_or(DEFAULT_SATURATION),
+        "brightness": DEFAULT_BRIGHTNESS,
+        "contrast": DEFAULT_CONTRAST,
+        "dither_algorithm": DEFAULT_DITHER_ALGORITHM,
+    })
+}
+
+/// Migrates a v1 entry to v2 by adding the explicit `version` tag. No field
+/// shapes changed in this step.
+fn migrate_v1_to_v2(mut value: serde_json::Value) -> serde_json::Value {
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("version".to_string(), serde_json::json!(2));
+    }
+    value
+}
+
+/// Migrates a v2 entry to v3 by adding the `content_hash` field, absent
+/// (and unknown) for every image uploaded before content-addressed dedup
+/// existed.
+fn migrate_v2_to_v3(mut value: serde_json::Value) -> serde_json::Value {
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("version".to_string(), serde_json::json!(3));
+        obj.entry("content_hash").or_insert(serde_json::Value::Null);
+    }
+    value
+}
+
+/// Migrates a v3 entry to v4 by adding the `blurhash` field, absent (and
+/// unknown) for every image uploaded before BlurHash placeholders existed.
+fn migrate_v3_to_v4(mut value: serde_json::Value) -> serde_json::Value {
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("version".to_string(), serde_json::json!(4));
+        obj.entry("blurhash").or_insert(serde_json::Value::Null);
+    }
+    value
+}
+
+/// Migrates a v4 entry to v5 by adding the `upload_hash` field, absent (and
+/// unknown) for every image uploaded before raw-byte ingest hashing existed.
+fn migrate_v4_to_v5(mut value: serde_json::Value) -> serde_json::Value {
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("version".to_string(), serde_json::json!(5));
+        obj.entry("upload_hash").or_insert(serde_json::Value::Null);
+    }
+    value
+}
+
+/// Migrates a v5 entry to v6 by adding the `perceptual_hash` field, absent
+/// (and unknown) for every image uploaded before dHash-based near-duplicate
+/// detection existed.
+fn migrate_v5_to_v6(mut value: serde_json::Value) -> serde_json::Value {
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("version".to_string(), serde_json::json!(6));
+        obj.entry("perceptual_hash").or_insert(serde_json::Value::Null);
+    }
+    value
+}
+
+/// Ordered chain of `vN -> vN+1` migrations, indexed by the version being
+/// migrated *from*. Applied in order until a value reaches `CURRENT_VERSION`.
+const MIGRATIONS: &[fn(serde_json::Value) -> serde_json::Value] = &[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+];
+
+/// Runs `value` through every migration step needed to reach `CURRENT_VERSION`.
+fn migrate_to_current(mut value: serde_json::Value, version: u32) -> serde_json::Value {
+    let mut version = version as usize;
+    while version < MIGRATIONS.len() {
+        value = MIGRATIONS[version](value);
+        version += 1;
+    }
+    value
+}
+
+/// Detects the schema version of a raw per-file metadata value. Entries
+/// missing an explicit `version` tag predate it and are v1, the original
+/// per-file shape; v0 (the legacy single-file format) never reaches this
+/// function as a per-file value, since it's migrated in bulk by
+/// `migrate_legacy_metadata`.
+fn detect_version(value: &serde_json::Value) -> u32 {
+    value
+        .get("version")
+        .and_then(|v| v.as_u64())
+        .map(|v| v as u32)
+        .unwrap_or(1)
+}
+
 /// Ensures the metadata directory exists.
 pub fn ensure_metadata_dir() {
     let path = config::metadata_dir();
@@ -85,6 +234,43 @@ fn get_metadata_path(filename: &str) -> PathBuf {
     config::metadata_dir().join(format!("{}.json", filename))
 }
 
+/// Cached snapshot of the metadata directory listing, keyed on the
+/// directory's mtime and size. See `get_all_filenames` for how it's used
+/// and invalidated.
+struct DirListingCache {
+    mtime: SystemTime,
+    /// A plain `stat()` doesn't expose an entry count, but on the filesystems
+    /// this runs on, directory size grows and shrinks as entries are added
+    /// and removed, so it doubles as a cheap secondary change signal.
+    dir_size: u64,
+    filenames: Vec<String>,
+}
+
+static DIR_LISTING_CACHE: LazyLock<Mutex<Option<DirListingCache>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Invalidates the cached directory listing. Called whenever a metadata
+/// file is added or removed.
+fn invalidate_dir_listing_cache() {
+    *DIR_LISTING_CACHE.lock().unwrap() = None;
+}
+
+/// Reads the metadata directory fresh, stripping `.json` to recover filenames.
+fn scan_metadata_dir(path: &Path) -> Vec<String> {
+    match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let filename = entry.file_name().to_string_lossy().to_string();
+                filename.strip_suffix(".json").map(|s| s.to_string())
+            })
+            .collect(),
+        Err(e) => {
+            error!("Failed to read metadata directory: {}", e);
+            Vec::new()
+        }
+    }
+}
+
 /// Loads metadata for an image. Returns default if file doesn't exist.
 pub fn load_metadata(filename: &str) -> ImageMetadata {
     let path = get_metadata_path(filename);
@@ -93,52 +279,155 @@ pub fn load_metadata(filename: &str) -> ImageMetadata {
         return ImageMetadata::default();
     }
 
-    match fs::read_to_string(&path) {
-        Ok(contents) => match serde_json::from_str(&contents) {
-            Ok(metadata) => metadata,
-            Err(e) => {
-                warn!(
-                    "Failed to parse metadata for '{}', using defaults: {}",
-                    filename, e
-                );
-                ImageMetadata::default()
-            }
-        },
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
         Err(e) => {
             warn!(
                 "Failed to read metadata for '{}', using defaults: {}",
                 filename, e
             );
+            return ImageMetadata::default();
+        }
+    };
+
+    // Parse as a raw value first so an old or reshaped schema can be
+    // migrated before deserializing into the current struct, rather than
+    // failing the deserialize and silently discarding the user's settings.
+    let value: serde_json::Value = match serde_json::from_str(&contents) {
+        Ok(value) => value,
+        Err(e) => {
+            warn!(
+                "Failed to parse metadata for '{}', using defaults: {}",
+                filename, e
+            );
+            return ImageMetadata::default();
+        }
+    };
+
+    let version = detect_version(&value);
+    let migrated = migrate_to_current(value, version);
+
+    match serde_json::from_value::<ImageMetadata>(migrated) {
+        Ok(metadata) => {
+            if version < CURRENT_VERSION {
+                info!(
+                    "Migrated metadata for '{}' from version {} to {}",
+                    filename, version, CURRENT_VERSION
+                );
+                if let Err(e) = save_metadata(filename, &metadata) {
+                    warn!("Failed to persist migrated metadata for '{}': {}", filename, e);
+                }
+            }
+            metadata
+        }
+        Err(e) => {
+            warn!(
+                "Failed to deserialize migrated metadata for '{}' (detected version {}), using defaults: {}",
+                filename, version, e
+            );
             ImageMetadata::default()
         }
     }
 }
 
-/// Saves metadata for an image atomically.
-pub fn save_metadata(filename: &str, metadata: &ImageMetadata) {
+/// Builds a unique temp-file suffix so two concurrent savers for the same
+/// image never race on the same temp path.
+fn unique_temp_suffix() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    format!("{}-{}", std::process::id(), nanos)
+}
+
+/// fsyncs a directory so a rename into it is durable across a crash.
+fn fsync_dir(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Saves metadata for an image atomically: write to a uniquely-named temp
+/// file in the same directory, fsync it, rename it over the target, then
+/// fsync the parent directory so the rename itself is durable across power
+/// loss.
+pub fn save_metadata(filename: &str, metadata: &ImageMetadata) -> Result<(), String> {
     ensure_metadata_dir();
 
+    let dir = config::metadata_dir();
     let path = get_metadata_path(filename);
-    let temp_path = path.with_extension("json.tmp");
-
-    match serde_json::to_string_pretty(metadata) {
-        Ok(json) => {
-            // Write to temp file first.
-            if let Err(e) = fs::write(&temp_path, &json) {
-                error!("Failed to write temp metadata for '{}': {}", filename, e);
-                return;
-            }
+    let temp_path = dir.join(format!("{}.json.tmp.{}", filename, unique_temp_suffix()));
+
+    let json = serde_json::to_string_pretty(metadata)
+        .map_err(|e| format!("Failed to serialize metadata for '{}': {}", filename, e))?;
+
+    let write_result: std::io::Result<()> = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()
+    })();
+
+    if let Err(e) = write_result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!(
+            "Failed to write temp metadata for '{}': {}",
+            filename, e
+        ));
+    }
 
-            // Atomically rename temp file to target file.
-            if let Err(e) = fs::rename(&temp_path, &path) {
-                error!("Failed to rename metadata file for '{}': {}", filename, e);
-                let _ = fs::remove_file(&temp_path);
-            }
+    if let Err(e) = fs::rename(&temp_path, &path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!(
+            "Failed to rename metadata file for '{}': {}",
+            filename, e
+        ));
+    }
+
+    // The rename itself is what needs to survive a crash; a failure here
+    // only weakens that durability guarantee, it doesn't undo the rename,
+    // so it's logged rather than surfaced as a save failure.
+    if let Err(e) = fsync_dir(&dir) {
+        warn!(
+            "Failed to fsync metadata directory after saving '{}': {}",
+            filename, e
+        );
+    }
+
+    invalidate_dir_listing_cache();
+    Ok(())
+}
+
+/// Removes stray temp files left behind by a crash mid-write
+/// (`<name>.json.tmp.<pid>-<nanos>`). Call once at startup.
+pub fn sweep_orphaned_temp_files() {
+    let dir = config::metadata_dir();
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return;
+    };
+
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_orphaned_temp_file = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|name| name.contains(".json.tmp."));
+
+        if !is_orphaned_temp_file {
+            continue;
         }
-        Err(e) => {
-            error!("Failed to serialize metadata for '{}': {}", filename, e);
+
+        match fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) => warn!(
+                "Failed to remove orphaned metadata temp file '{}': {}",
+                path.display(),
+                e
+            ),
         }
     }
+
+    if removed > 0 {
+        info!("Swept {} orphaned metadata temp file(s)", removed);
+    }
 }
 
 /// Deletes metadata for an image.
@@ -147,6 +436,8 @@ pub fn delete_metadata(filename: &str) {
     if path.exists() {
         if let Err(e) = fs::remove_file(&path) {
             error!("Failed to delete metadata for '{}': {}", filename, e);
+        } else {
+            invalidate_dir_listing_cache();
         }
     }
 }
@@ -164,6 +455,19 @@ fn is_valid_dither_algorithm(algorithm: &str) -> bool {
     matches!(algorithm, "floyd-steinberg" | "atkinson" | "ordered")
 }
 
+/// Maps a saved filter name to the `image` crate's resize filter, falling
+/// back to the same bicubic-equivalent default as an unrecognized or
+/// invalid name would use elsewhere.
+pub fn parse_filter(filter: &str) -> image::imageops::FilterType {
+    match filter {
+        "nearest" => image::imageops::FilterType::Nearest,
+        "bilinear" => image::imageops::FilterType::Triangle,
+        "mitchell" => image::imageops::FilterType::Gaussian,
+        "lanczos" => image::imageops::FilterType::Lanczos3,
+        _ => image::imageops::FilterType::CatmullRom,
+    }
+}
+
 /// Gets the filter preference for an image.
 pub fn get_filter_for_image(filename: &str) -> String {
     let metadata = load_metadata(filename);
@@ -179,7 +483,8 @@ pub fn get_filter_for_image(filename: &str) -> String {
     }
 }
 
-/// Saves all settings for an image.
+/// Saves all settings for an image, preserving any fields (like
+/// `content_hash`) that aren't part of this call.
 pub fn save_all_settings(
     filename: &str,
     filter: &str,
@@ -187,15 +492,14 @@ pub fn save_all_settings(
     brightness: i32,
     contrast: i32,
     dither_algorithm: &str,
-) {
-    let metadata = ImageMetadata {
-        filter: filter.to_string(),
-        saturation,
-        brightness,
-        contrast,
-        dither_algorithm: dither_algorithm.to_string(),
-    };
-    save_metadata(filename, &metadata);
+) -> Result<(), String> {
+    let mut metadata = load_metadata(filename);
+    metadata.filter = filter.to_string();
+    metadata.saturation = saturation;
+    metadata.brightness = brightness;
+    metadata.contrast = contrast;
+    metadata.dither_algorithm = dither_algorithm.to_string();
+    save_metadata(filename, &metadata)
 }
 
 /// Gets the saturation for an image.
@@ -226,36 +530,224 @@ pub fn save_dither_settings(
     brightness: i32,
     contrast: i32,
     dither_algorithm: &str,
-) {
+) -> Result<(), String> {
     let mut metadata = load_metadata(filename);
     metadata.saturation = saturation;
     metadata.brightness = brightness;
     metadata.contrast = contrast;
     metadata.dither_algorithm = dither_algorithm.to_string();
-    save_metadata(filename, &metadata);
+    save_metadata(filename, &metadata)
+}
+
+/// Records the SHA-256 of an image's decoded pixel bytes, computed by the
+/// upload pipeline for content-addressed deduplication.
+pub fn save_content_hash(filename: &str, content_hash: &str) -> Result<(), String> {
+    let mut metadata = load_metadata(filename);
+    metadata.content_hash = Some(content_hash.to_string());
+    save_metadata(filename, &metadata)
+}
+
+/// Records the BlurHash placeholder string for an image, computed by the
+/// upload pipeline.
+pub fn save_blurhash(filename: &str, blurhash: &str) -> Result<(), String> {
+    let mut metadata = load_metadata(filename);
+    metadata.blurhash = Some(blurhash.to_string());
+    save_metadata(filename, &metadata)
+}
+
+/// Looks up the filename of an existing image whose decoded content hash
+/// matches `content_hash`, if any. Used to deduplicate uploads: a match
+/// means the incoming file is the same picture (possibly re-encoded or
+/// renamed) as one already in the gallery.
+pub fn find_by_content_hash(content_hash: &str) -> Option<String> {
+    load_all_metadata()
+        .into_iter()
+        .find(|(_, meta)| meta.content_hash.as_deref() == Some(content_hash))
+        .map(|(filename, _)| filename)
+}
+
+/// Records the SHA-256 of an image's raw uploaded bytes, computed while the
+/// upload is staged to disk.
+pub fn save_upload_hash(filename: &str, upload_hash: &str) -> Result<(), String> {
+    let mut metadata = load_metadata(filename);
+    metadata.upload_hash = Some(upload_hash.to_string());
+    save_metadata(filename, &metadata)
+}
+
+/// Looks up the filename of an existing image whose raw upload hash matches
+/// `upload_hash`, if any. A match means the incoming file is byte-for-byte
+/// identical to one already in the gallery, so the upload pipeline can skip
+/// decoding it entirely.
+pub fn find_by_upload_hash(upload_hash: &str) -> Option<String> {
+    load_all_metadata()
+        .into_iter()
+        .find(|(_, meta)| meta.upload_hash.as_deref() == Some(upload_hash))
+        .map(|(filename, _)| filename)
+}
+
+/// Records the dHash perceptual fingerprint for an image, computed by the
+/// upload pipeline.
+pub fn save_perceptual_hash(filename: &str, perceptual_hash: u64) -> Result<(), String> {
+    let mut metadata = load_metadata(filename);
+    metadata.perceptual_hash = Some(perceptual_hash);
+    save_metadata(filename, &metadata)
+}
+
+/// Groups every image with a saved perceptual hash into clusters of mutual
+/// near-duplicates, per `phash::is_near_duplicate`. Each image appears in
+/// exactly one cluster; singletons (no near-duplicate found) are omitted,
+/// since a cluster of one isn't actionable for `cleanup`.
+///
+/// Clustering is greedy rather than exhaustive: the first image in iteration
+/// order seeds a cluster, and any unclaimed image within the threshold of
+/// it joins; a handful of hashes missing a borderline match to a later
+/// cluster is an acceptable trade for keeping this O(n^2) in the common
+/// case of a modest-sized library rather than needing full graph matching.
+pub fn find_duplicate_clusters() -> Vec<Vec<String>> {
+    let hashes: Vec<(String, u64)> = load_all_metadata()
+        .into_iter()
+        .filter_map(|(filename, meta)| meta.perceptual_hash.map(|hash| (filename, hash)))
+        .collect();
+
+    let mut claimed = vec![false; hashes.len()];
+    let mut clusters = Vec::new();
+
+    for i in 0..hashes.len() {
+        if claimed[i] {
+            continue;
+        }
+
+        let mut cluster = vec![hashes[i].0.clone()];
+        claimed[i] = true;
+
+        for (j, (filename, hash)) in hashes.iter().enumerate().skip(i + 1) {
+            if !claimed[j] && phash::is_near_duplicate(hashes[i].1, *hash) {
+                cluster.push(filename.clone());
+                claimed[j] = true;
+            }
+        }
+
+        if cluster.len() > 1 {
+            clusters.push(cluster);
+        }
+    }
+
+    clusters
 }
 
 /// Returns a list of all filenames that have metadata files.
+///
+/// Re-reading the metadata directory on every call gets expensive as the
+/// library grows, so the listing is cached against the directory's mtime
+/// and size. A cache hit skips the directory read entirely.
 pub fn get_all_filenames() -> Vec<String> {
     let path = config::metadata_dir();
     if !path.exists() {
         return Vec::new();
     }
 
-    match fs::read_dir(&path) {
-        Ok(entries) => entries
-            .filter_map(|entry| {
-                let entry = entry.ok()?;
-                let filename = entry.file_name().to_string_lossy().to_string();
-                // Strip .json extension to get original filename.
-                filename.strip_suffix(".json").map(|s| s.to_string())
-            })
-            .collect(),
+    let dir_stat = match fs::metadata(&path) {
+        Ok(stat) => stat,
         Err(e) => {
-            error!("Failed to read metadata directory: {}", e);
-            Vec::new()
+            error!("Failed to stat metadata directory: {}", e);
+            return scan_metadata_dir(&path);
+        }
+    };
+
+    let mtime = match dir_stat.modified() {
+        Ok(mtime) => mtime,
+        Err(_) => return scan_metadata_dir(&path),
+    };
+    let dir_size = dir_stat.len();
+
+    {
+        let cache = DIR_LISTING_CACHE.lock().unwrap();
+        if let Some(cached) = cache.as_ref() {
+            if cached.mtime == mtime && cached.dir_size == dir_size {
+                return cached.filenames.clone();
+            }
         }
     }
+
+    let filenames = scan_metadata_dir(&path);
+
+    // Guard against the second-granularity mtime race: if the directory was
+    // touched within the same wall-clock second as this scan (or its mtime
+    // is somehow in the future), a write landing in that same window could
+    // be invisible to a later mtime comparison. Don't cache in that case, so
+    // the next call re-scans instead of trusting a possibly-stale snapshot.
+    let ambiguous = SystemTime::now()
+        .duration_since(mtime)
+        .map(|elapsed| elapsed < Duration::from_secs(1))
+        .unwrap_or(true);
+
+    let mut cache = DIR_LISTING_CACHE.lock().unwrap();
+    *cache = if ambiguous {
+        None
+    } else {
+        Some(DirListingCache {
+            mtime,
+            dir_size,
+            filenames: filenames.clone(),
+        })
+    };
+
+    filenames
+}
+
+/// Caps how many metadata files are read concurrently in `load_all_metadata`.
+/// This workload is I/O-bound: past ~16 concurrent reads, throughput flattens
+/// and context-switching dominates, so spawning one thread per file (or
+/// scaling with core count) doesn't help and thrashes the filesystem on
+/// large libraries.
+const METADATA_LOAD_PARALLELISM: usize = 16;
+
+/// Loads every image's metadata concurrently via a small bounded pool of
+/// worker threads, instead of one-at-a-time via repeated `load_metadata`.
+/// A corrupt or unreadable file falls back to `ImageMetadata::default()`
+/// with a warning, exactly like `load_metadata`, so it never sinks the batch.
+pub fn load_all_metadata() -> HashMap<String, ImageMetadata> {
+    let filenames = get_all_filenames();
+    if filenames.is_empty() {
+        return HashMap::new();
+    }
+
+    let worker_count = METADATA_LOAD_PARALLELISM.min(filenames.len());
+    let chunk_size = filenames.len().div_ceil(worker_count);
+
+    std::thread::scope(|scope| {
+        filenames
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|filename| (filename.clone(), load_metadata(filename)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("metadata load worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Like `load_all_metadata`, but applies the same filter/dither-algorithm
+/// validation as `get_all_metadata` to every entry.
+pub fn get_all_metadata_map() -> HashMap<String, ImageMetadata> {
+    load_all_metadata()
+        .into_iter()
+        .map(|(filename, mut metadata)| {
+            if !is_valid_filter(&metadata.filter) {
+                metadata.filter = DEFAULT_FILTER.to_string();
+            }
+            if !is_valid_dither_algorithm(&metadata.dither_algorithm) {
+                metadata.dither_algorithm = DEFAULT_DITHER_ALGORITHM.to_string();
+            }
+            (filename, metadata)
+        })
+        .collect()
 }
 
 /// Removes metadata files for images that no longer exist.
@@ -306,8 +798,9 @@ pub fn migrate_legacy_metadata() {
         }
     };
 
-    // Parse the legacy format.
-    let legacy_data: HashMap<String, LegacyImageMetadata> = match serde_json::from_str(&contents) {
+    // Parse the legacy format as raw values; each entry is schema version 0
+    // and runs through the same migration chain a per-file v0 entry would.
+    let legacy_data: HashMap<String, serde_json::Value> = match serde_json::from_str(&contents) {
         Ok(data) => data,
         Err(e) => {
             error!("Failed to parse legacy metadata file: {}", e);
@@ -320,16 +813,17 @@ pub fn migrate_legacy_metadata() {
 
     // Migrate each entry.
     let mut migrated = 0;
-    for (filename, legacy) in legacy_data {
-        let metadata = ImageMetadata {
-            filter: legacy.filter,
-            saturation: legacy.last_dithered_saturation.unwrap_or(DEFAULT_SATURATION),
-            brightness: DEFAULT_BRIGHTNESS,
-            contrast: DEFAULT_CONTRAST,
-            dither_algorithm: DEFAULT_DITHER_ALGORITHM.to_string(),
-        };
-        save_metadata(&filename, &metadata);
-        migrated += 1;
+    for (filename, legacy_value) in legacy_data {
+        let current_value = migrate_to_current(legacy_value, 0);
+        match serde_json::from_value::<ImageMetadata>(current_value) {
+            Ok(metadata) => match save_metadata(&filename, &metadata) {
+                Ok(()) => migrated += 1,
+                Err(e) => error!("Failed to save migrated metadata for '{}': {}", filename, e),
+            },
+            Err(e) => {
+                error!("Failed to migrate legacy metadata for '{}': {}", filename, e);
+            }
+        }
     }
 
     // Backup the legacy file.
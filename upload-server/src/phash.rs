@@ -0,0 +1,85 @@
+//! Perceptual hashing for near-duplicate detection.
+//!
+//! Unlike `processor::content_hash` (exact pixel match) and `upload_hash`
+//! (exact byte match), this catches the same picture re-uploaded after a
+//! resize, re-encode, or minor edit. Uses dHash: shrink to 9x8 grayscale and
+//! record, for each row, whether each pixel is brighter than its right-hand
+//! neighbor. The resulting 64-bit fingerprint is stable under small changes
+//! in scale and compression, and two fingerprints' similarity is just their
+//! Hamming distance.
+
+use image::DynamicImage;
+
+/// Computes the 64-bit dHash fingerprint of an image.
+pub fn compute(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, image::imageops::FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash <<= 1;
+            if left > right {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+/// Number of differing bits between two fingerprints. 0 means identical;
+/// larger values mean less similar.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Default Hamming-distance threshold at or below which two images are treated as
+/// near-duplicates, overridable via `INKY_SOUP_PHASH_THRESHOLD` for
+/// libraries that need a stricter or looser match.
+pub const DEFAULT_THRESHOLD: u32 = 5;
+
+/// Reads the configured near-duplicate threshold.
+pub fn threshold() -> u32 {
+    std::env::var("INKY_SOUP_PHASH_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_THRESHOLD)
+}
+
+/// Whether two fingerprints are close enough to be considered the same
+/// picture, per `threshold`.
+pub fn is_near_duplicate(a: u64, b: u64) -> bool {
+    hamming_distance(a, b) <= threshold()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hamming_distance_identical() {
+        assert_eq!(hamming_distance(0xABCD, 0xABCD), 0);
+    }
+
+    #[test]
+    fn test_hamming_distance_counts_differing_bits() {
+        assert_eq!(hamming_distance(0b1010, 0b0010), 1);
+        assert_eq!(hamming_distance(0b1111, 0b0000), 4);
+    }
+
+    #[test]
+    fn test_is_near_duplicate_respects_threshold() {
+        assert!(is_near_duplicate(0, 0));
+        assert!(!is_near_duplicate(0, u64::MAX));
+    }
+
+    #[test]
+    fn test_compute_stable_across_resize() {
+        let img = DynamicImage::new_rgb8(64, 64);
+        let a = compute(&img);
+        let resized = img.resize_exact(32, 32, image::imageops::FilterType::Triangle);
+        let b = compute(&resized);
+        assert_eq!(hamming_distance(a, b), 0);
+    }
+}
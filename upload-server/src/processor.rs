@@ -0,0 +1,208 @@
+//! Server-side rendering of the Inky Impression's dithered display image.
+//!
+//! Mirrors the browser-side canvas pipeline (brightness/contrast/saturation
+//! adjustment, then dithering against the panel's fixed palette) so thin
+//! clients and scripted batch jobs can get `/api/upload-dithered`'s output
+//! without a canvas.
+
+use image::{DynamicImage, Rgb, RgbImage};
+use sha2::{Digest, Sha256};
+
+/// Approximate sRGB values for the Inky Impression's fixed 7-color ACeP
+/// palette. The 6-color panel variant is the same minus orange.
+const SEVEN_COLOR_PALETTE: [[u8; 3]; 7] = [
+    [0, 0, 0],       // black
+    [255, 255, 255], // white
+    [255, 0, 0],     // red
+    [0, 200, 0],     // green
+    [0, 0, 255],     // blue
+    [255, 255, 0],   // yellow
+    [255, 140, 0],   // orange
+];
+
+const SIX_COLOR_PALETTE: [[u8; 3]; 6] = [
+    [0, 0, 0],
+    [255, 255, 255],
+    [255, 0, 0],
+    [0, 200, 0],
+    [0, 0, 255],
+    [255, 255, 0],
+];
+
+/// Hashes an image's decoded pixel bytes (not its file encoding), so the
+/// same picture re-uploaded in a different lossless encoding still hashes
+/// identically for content-addressed dedup. A lossy re-compression changes
+/// the pixels, and with them the hash.
+pub fn content_hash(img: &DynamicImage) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(img.to_rgba8().into_raw());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Picks the palette for the display's configured color mode. Anything
+/// other than the six-color panel variant gets the full seven-color
+/// palette, matching the default 7-color Inky Impression.
+pub fn palette_for(color_mode: &str) -> &'static [[u8; 3]] {
+    if color_mode.eq_ignore_ascii_case("six-color") || color_mode.eq_ignore_ascii_case("6-color") {
+        &SIX_COLOR_PALETTE
+    } else {
+        &SEVEN_COLOR_PALETTE
+    }
+}
+
+/// Renders `img` for the e-ink panel: brightness/contrast/saturation
+/// adjustment followed by dithering against `palette`. `dither_algorithm`
+/// selects the dithering pass ("ordered" for Bayer 8x8; anything else,
+/// including "floyd-steinberg" and "atkinson", falls back to Floyd-Steinberg
+/// error diffusion, since Atkinson dithering isn't implemented yet).
+pub fn render(
+    img: &RgbImage,
+    palette: &[[u8; 3]],
+    saturation: f32,
+    brightness: i32,
+    contrast: i32,
+    dither_algorithm: &str,
+) -> RgbImage {
+    let adjusted = adjust(img, saturation, brightness, contrast);
+
+    match dither_algorithm {
+        "ordered" => dither_ordered(&adjusted, palette),
+        _ => dither_floyd_steinberg(&adjusted, palette),
+    }
+}
+
+/// Applies brightness/contrast/saturation, matching the browser-side
+/// canvas sliders: `saturation` is a 0..1 control where 0.5 is neutral
+/// (unchanged), 0 is grayscale, and 1 is double saturation;
+/// `brightness`/`contrast` are signed offsets where 0 is neutral.
+fn adjust(img: &RgbImage, saturation: f32, brightness: i32, contrast: i32) -> RgbImage {
+    let saturation_factor = saturation * 2.0;
+    let contrast = contrast as f32;
+    let contrast_factor = (259.0 * (contrast + 255.0)) / (255.0 * (259.0 - contrast));
+    let brightness = brightness as f32;
+
+    let mut out = RgbImage::new(img.width(), img.height());
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let [r, g, b] = pixel.0;
+        let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+
+        let adjust_channel = |c: u8| -> u8 {
+            let saturated = luma + (c as f32 - luma) * saturation_factor;
+            let contrasted = contrast_factor * (saturated - 128.0) + 128.0;
+            (contrasted + brightness).round().clamp(0.0, 255.0) as u8
+        };
+
+        out.put_pixel(x, y, Rgb([adjust_channel(r), adjust_channel(g), adjust_channel(b)]));
+    }
+
+    out
+}
+
+/// Finds the palette color nearest `pixel` by squared RGB distance.
+fn nearest_palette_color(pixel: [f32; 3], palette: &[[u8; 3]]) -> [u8; 3] {
+    palette
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            squared_distance(pixel, *a)
+                .partial_cmp(&squared_distance(pixel, *b))
+                .unwrap()
+        })
+        .unwrap_or([0, 0, 0])
+}
+
+fn squared_distance(pixel: [f32; 3], color: [u8; 3]) -> f32 {
+    let dr = pixel[0] - color[0] as f32;
+    let dg = pixel[1] - color[1] as f32;
+    let db = pixel[2] - color[2] as f32;
+    dr * dr + dg * dg + db * db
+}
+
+/// Floyd-Steinberg error-diffusion dithering against `palette`: walk pixels
+/// in raster order, quantize each to its nearest palette color, then
+/// diffuse the per-channel quantization error to the right (7/16),
+/// bottom-left (3/16), bottom (5/16), and bottom-right (1/16) neighbors,
+/// skipping neighbors that fall outside the image.
+fn dither_floyd_steinberg(img: &RgbImage, palette: &[[u8; 3]]) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let mut buf: Vec<[f32; 3]> = img
+        .pixels()
+        .map(|p| [p.0[0] as f32, p.0[1] as f32, p.0[2] as f32])
+        .collect();
+
+    let mut out = RgbImage::new(width, height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let idx = (y * width + x) as usize;
+            let old = buf[idx];
+            let new = nearest_palette_color(old, palette);
+            out.put_pixel(x, y, Rgb(new));
+
+            let error = [
+                old[0] - new[0] as f32,
+                old[1] - new[1] as f32,
+                old[2] - new[2] as f32,
+            ];
+
+            let mut diffuse = |dx: i32, dy: i32, weight: f32| {
+                let nx = x as i32 + dx;
+                let ny = y as i32 + dy;
+                if nx < 0 || nx >= width as i32 || ny < 0 || ny >= height as i32 {
+                    return;
+                }
+
+                let nidx = (ny as u32 * width + nx as u32) as usize;
+                for c in 0..3 {
+                    buf[nidx][c] = (buf[nidx][c] + error[c] * weight).clamp(0.0, 255.0);
+                }
+            };
+
+            diffuse(1, 0, 7.0 / 16.0);
+            diffuse(-1, 1, 3.0 / 16.0);
+            diffuse(0, 1, 5.0 / 16.0);
+            diffuse(1, 1, 1.0 / 16.0);
+        }
+    }
+
+    out
+}
+
+/// Standard 8x8 Bayer threshold matrix, values 0..63.
+const BAYER_8X8: [[i32; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Ordered (Bayer 8x8) dithering: perturb each pixel by a small bias drawn
+/// from the threshold matrix before quantizing to the nearest palette
+/// color, so flat regions break up into a repeating dot pattern instead of
+/// banding.
+fn dither_ordered(img: &RgbImage, palette: &[[u8; 3]]) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let mut out = RgbImage::new(width, height);
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = img.get_pixel(x, y);
+            // Center the 0..63 threshold on 0 so it perturbs in both directions.
+            let bias = (BAYER_8X8[(y % 8) as usize][(x % 8) as usize] - 32) as f32;
+
+            let perturbed = [
+                pixel.0[0] as f32 + bias,
+                pixel.0[1] as f32 + bias,
+                pixel.0[2] as f32 + bias,
+            ];
+
+            out.put_pixel(x, y, Rgb(nearest_palette_color(perturbed, palette)));
+        }
+    }
+
+    out
+}
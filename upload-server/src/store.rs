@@ -0,0 +1,334 @@
+//! Pluggable storage backend for the image directories (originals, cache,
+//! dithered, thumbs).
+//!
+//! `ImageStore` abstracts over where an image tree's bytes actually live,
+//! so upload/gallery/delete handlers don't need to know whether a file is on
+//! the local SD card or in S3-compatible object storage. `FilesystemStore`
+//! preserves today's behavior; `S3Store` lets originals/cache/dithered be
+//! offloaded to remote storage (so multiple inky-soup frontends, or a
+//! headless flasher, can share one repository, and the Pi's SD card stops
+//! taking the wear of holding every original). Thumbnails always stay on
+//! `FilesystemStore` regardless of backend, since they're small, served
+//! constantly by the gallery, and worth keeping local for speed.
+//!
+//! Configured entirely via env vars (`INKY_SOUP_STORAGE_BACKEND` and
+//! `INKY_SOUP_S3_*`), matching the rest of this crate's non-display tunables
+//! (see `cache_worker`'s `CACHE_BUDGET_BYTES` etc.) rather than
+//! `display.conf`, which is reserved for physical display properties.
+
+use glob::glob;
+use log::error;
+use rocket::async_trait;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Size and recency of a stored key, used by the cleanup subsystem's LRU
+/// retention pass. `last_accessed` prefers true access time where the
+/// backend has one, falling back to a modification/creation time otherwise
+/// (see each implementation's `stat`).
+#[derive(Debug, Clone, Copy)]
+pub struct ArtifactStat {
+    pub size: u64,
+    pub last_accessed: SystemTime,
+}
+
+/// A storage backend for one image tree. Keys are filenames, not paths -
+/// the store owns its own base location (a directory, or an S3 prefix).
+#[async_trait]
+pub trait ImageStore: Send + Sync {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), String>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>, String>;
+    async fn delete(&self, key: &str) -> Result<(), String>;
+    async fn list(&self) -> Result<Vec<String>, String>;
+    async fn exists(&self, key: &str) -> bool;
+    async fn stat(&self, key: &str) -> Result<ArtifactStat, String>;
+}
+
+/// Stores each image tree directly under a local directory - today's
+/// behavior, unchanged.
+pub struct FilesystemStore {
+    base_dir: PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+        let base_dir = base_dir.into();
+        if let Err(e) = std::fs::create_dir_all(&base_dir) {
+            error!("Failed to create store directory {:?}: {}", base_dir, e);
+        }
+        Self { base_dir }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.base_dir.join(key)
+    }
+}
+
+#[async_trait]
+impl ImageStore for FilesystemStore {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), String> {
+        let path = self.path_for(key);
+        tokio::fs::write(&path, bytes)
+            .await
+            .map_err(|e| format!("Failed to write {:?}: {}", path, e))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let path = self.path_for(key);
+        tokio::fs::read(&path)
+            .await
+            .map_err(|e| format!("Failed to read {:?}: {}", path, e))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        let path = self.path_for(key);
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(|e| format!("Failed to delete {:?}: {}", path, e))
+    }
+
+    async fn list(&self) -> Result<Vec<String>, String> {
+        let base = self.base_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let pattern = format!("{}/*", base.display());
+            let entries = glob(&pattern).map_err(|e| format!("Failed to glob {:?}: {}", base, e))?;
+
+            Ok(entries
+                .flatten()
+                .filter(|path| !path.is_dir())
+                .filter_map(|path| path.file_name().and_then(|f| f.to_str()).map(str::to_string))
+                .collect())
+        })
+        .await
+        .map_err(|e| format!("List task panicked: {}", e))?
+    }
+
+    async fn exists(&self, key: &str) -> bool {
+        self.path_for(key).exists()
+    }
+
+    async fn stat(&self, key: &str) -> Result<ArtifactStat, String> {
+        let path = self.path_for(key);
+        let meta = tokio::fs::metadata(&path)
+            .await
+            .map_err(|e| format!("Failed to stat {:?}: {}", path, e))?;
+
+        // Prefer true access time; some filesystems (or `noatime` mounts)
+        // don't track it, so fall back to modification time.
+        let last_accessed = meta.accessed().or_else(|_| meta.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+
+        Ok(ArtifactStat {
+            size: meta.len(),
+            last_accessed,
+        })
+    }
+}
+
+/// Connection details for an S3-compatible bucket, read by
+/// `get_storage_config`.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// Custom endpoint for S3-compatible providers (MinIO, Backblaze, R2).
+    /// `None` uses AWS's standard regional endpoint.
+    pub endpoint: Option<String>,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+/// S3-compatible object storage backend. Each instance is scoped to one
+/// "tree" (originals, cache, or dithered) via a key prefix, so all three
+/// can share a bucket without colliding.
+pub struct S3Store {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3Store {
+    pub fn new(config: &S3Config, prefix: &str) -> Self {
+        let credentials = aws_sdk_s3::config::Credentials::new(
+            &config.access_key,
+            &config.secret_key,
+            None,
+            None,
+            "inky-soup",
+        );
+
+        let mut builder = aws_sdk_s3::config::Builder::new()
+            .region(aws_sdk_s3::config::Region::new(config.region.clone()))
+            .credentials_provider(credentials)
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest());
+
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.endpoint_url(endpoint).force_path_style(true);
+        }
+
+        Self {
+            client: aws_sdk_s3::Client::from_conf(builder.build()),
+            bucket: config.bucket.clone(),
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        format!("{}/{}", self.prefix, key)
+    }
+}
+
+#[async_trait]
+impl ImageStore for S3Store {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), String> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .body(bytes.into())
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("S3 put failed for {}: {}", key, e))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .send()
+            .await
+            .map_err(|e| format!("S3 get failed for {}: {}", key, e))?;
+
+        output
+            .body
+            .collect()
+            .await
+            .map(|data| data.into_bytes().to_vec())
+            .map_err(|e| format!("S3 read failed for {}: {}", key, e))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("S3 delete failed for {}: {}", key, e))
+    }
+
+    async fn list(&self) -> Result<Vec<String>, String> {
+        let mut keys = Vec::new();
+        let mut continuation_token = None;
+        let key_prefix = format!("{}/", self.prefix);
+
+        loop {
+            let mut request = self.client.list_objects_v2().bucket(&self.bucket).prefix(&key_prefix);
+            if let Some(token) = continuation_token.take() {
+                request = request.continuation_token(token);
+            }
+
+            let output = request.send().await.map_err(|e| format!("S3 list failed: {}", e))?;
+
+            for object in output.contents() {
+                if let Some(name) = object.key().and_then(|k| k.strip_prefix(&key_prefix)) {
+                    keys.push(name.to_string());
+                }
+            }
+
+            if output.is_truncated().unwrap_or(false) {
+                continuation_token = output.next_continuation_token().map(str::to_string);
+            } else {
+                break;
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn exists(&self, key: &str) -> bool {
+        self.client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .send()
+            .await
+            .is_ok()
+    }
+
+    async fn stat(&self, key: &str) -> Result<ArtifactStat, String> {
+        let output = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.object_key(key))
+            .send()
+            .await
+            .map_err(|e| format!("S3 head failed for {}: {}", key, e))?;
+
+        // S3 has no access-time concept, so `last_modified` (when the
+        // object was last put) is the best recency signal available.
+        let last_accessed = output
+            .last_modified()
+            .map(|dt| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(dt.secs().max(0) as u64))
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        Ok(ArtifactStat {
+            size: output.content_length().unwrap_or(0).max(0) as u64,
+            last_accessed,
+        })
+    }
+}
+
+/// The four image trees the upload/gallery/delete handlers operate on.
+/// Cheap to clone: every field is an `Arc`, so the background upload queue
+/// worker (see `upload_queue`) can hold its own copy alongside the one in
+/// managed state.
+#[derive(Clone)]
+pub struct ImageStores {
+    pub originals: Arc<dyn ImageStore>,
+    pub cache: Arc<dyn ImageStore>,
+    pub dithered: Arc<dyn ImageStore>,
+    pub thumbs: Arc<dyn ImageStore>,
+}
+
+/// Reads `INKY_SOUP_STORAGE_BACKEND` ("filesystem", the default, or "s3")
+/// and the matching `INKY_SOUP_S3_*` vars when it's "s3".
+fn get_s3_config() -> Option<S3Config> {
+    if std::env::var("INKY_SOUP_STORAGE_BACKEND").as_deref() != Ok("s3") {
+        return None;
+    }
+
+    Some(S3Config {
+        bucket: std::env::var("INKY_SOUP_S3_BUCKET").unwrap_or_default(),
+        region: std::env::var("INKY_SOUP_S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+        endpoint: std::env::var("INKY_SOUP_S3_ENDPOINT").ok(),
+        access_key: std::env::var("INKY_SOUP_S3_ACCESS_KEY").unwrap_or_default(),
+        secret_key: std::env::var("INKY_SOUP_S3_SECRET_KEY").unwrap_or_default(),
+    })
+}
+
+/// Builds the four image stores for the configured backend. Thumbnails
+/// always use the local filesystem; see the module doc comment for why.
+pub fn build_image_stores() -> ImageStores {
+    let thumbs: Arc<dyn ImageStore> = Arc::new(FilesystemStore::new("static/images/thumbs"));
+
+    match get_s3_config() {
+        Some(s3_config) => ImageStores {
+            originals: Arc::new(S3Store::new(&s3_config, "originals")),
+            cache: Arc::new(S3Store::new(&s3_config, "cache")),
+            dithered: Arc::new(S3Store::new(&s3_config, "dithered")),
+            thumbs,
+        },
+        None => ImageStores {
+            originals: Arc::new(FilesystemStore::new("static/images")),
+            cache: Arc::new(FilesystemStore::new("static/images/cache")),
+            dithered: Arc::new(FilesystemStore::new("static/images/dithered")),
+            thumbs,
+        },
+    }
+}
@@ -0,0 +1,273 @@
+//! Background queue for the slow part of an upload (decode, content-hash,
+//! BlurHash, perceptual hash, dedup check, and the final store commit), mirroring
+//! `flash_queue`'s queue-plus-worker shape so that work doesn't block the
+//! HTTP request on constrained hardware. `/api/upload-async` stages the
+//! upload to disk and returns a job id immediately; the worker below drains
+//! the queue one job at a time and `/api/upload-async/<job_id>` reports
+//! progress, matching how `flash_queue`/`jobs` expose theirs.
+//!
+//! Unlike the flash queue, a failed job isn't retried: the client still has
+//! the original bytes and can just re-submit.
+
+use crate::store::ImageStores;
+use crate::{blurhash, cache_worker, metadata, phash, processor};
+use log::{error, info};
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// Shared upload queue state type.
+pub type UploadQueueState = Arc<Mutex<UploadQueue>>;
+
+pub fn new_upload_queue_state() -> UploadQueueState {
+    Arc::new(Mutex::new(UploadQueue::new()))
+}
+
+/// Status of a backgrounded upload job.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum UploadJobStatus {
+    Pending,
+    Processing,
+    Done,
+    Failed,
+}
+
+/// A single backgrounded upload job, polled via `/api/upload-async/<job_id>`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UploadJob {
+    pub job_id: u64,
+    /// The filename the client uploaded under.
+    pub filename: String,
+    pub status: UploadJobStatus,
+    pub created_at: u64,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub error_message: Option<String>,
+    /// Gallery filename the upload ended up under once `status` is `Done`;
+    /// may differ from `filename` if the upload deduplicated against an
+    /// existing image.
+    pub result_filename: Option<String>,
+    /// True if `result_filename` refers to a pre-existing image rather than
+    /// this upload's own bytes.
+    pub deduplicated: bool,
+}
+
+/// Work still to be done for a queued job: the sanitized filename, the path
+/// of the already-staged upload, and its raw SHA-256 (computed while
+/// staging, see `ingest::stage_and_hash`) — written by the request handler
+/// before it returns so the worker never touches a `TempFile` about to be
+/// dropped.
+struct PendingUpload {
+    job_id: u64,
+    filename: String,
+    staging_path: String,
+    upload_hash: String,
+}
+
+/// Queue of backgrounded upload jobs and their status, drained one at a time
+/// by `spawn_upload_worker`.
+pub struct UploadQueue {
+    jobs: HashMap<u64, UploadJob>,
+    pending: VecDeque<PendingUpload>,
+    next_job_id: u64,
+}
+
+impl UploadQueue {
+    pub fn new() -> Self {
+        Self {
+            jobs: HashMap::new(),
+            pending: VecDeque::new(),
+            next_job_id: 1,
+        }
+    }
+
+    /// Enqueues a staged upload and returns its job id immediately.
+    pub fn enqueue(&mut self, filename: String, staging_path: String, upload_hash: String) -> u64 {
+        let job_id = self.next_job_id;
+        self.next_job_id += 1;
+
+        self.jobs.insert(
+            job_id,
+            UploadJob {
+                job_id,
+                filename: filename.clone(),
+                status: UploadJobStatus::Pending,
+                created_at: current_time_millis(),
+                started_at: None,
+                finished_at: None,
+                error_message: None,
+                result_filename: None,
+                deduplicated: false,
+            },
+        );
+        self.pending.push_back(PendingUpload {
+            job_id,
+            filename,
+            staging_path,
+            upload_hash,
+        });
+
+        job_id
+    }
+
+    /// Looks up a job's current status by id.
+    pub fn get(&self, job_id: u64) -> Option<UploadJob> {
+        self.jobs.get(&job_id).cloned()
+    }
+
+    fn dequeue(&mut self) -> Option<PendingUpload> {
+        self.pending.pop_front()
+    }
+
+    fn mark_processing(&mut self, job_id: u64) {
+        if let Some(job) = self.jobs.get_mut(&job_id) {
+            job.status = UploadJobStatus::Processing;
+            job.started_at = Some(current_time_millis());
+        }
+    }
+
+    fn mark_done(&mut self, job_id: u64, result_filename: String, deduplicated: bool) {
+        if let Some(job) = self.jobs.get_mut(&job_id) {
+            job.status = UploadJobStatus::Done;
+            job.finished_at = Some(current_time_millis());
+            job.result_filename = Some(result_filename);
+            job.deduplicated = deduplicated;
+        }
+    }
+
+    fn mark_failed(&mut self, job_id: u64, error: String) {
+        if let Some(job) = self.jobs.get_mut(&job_id) {
+            job.status = UploadJobStatus::Failed;
+            job.finished_at = Some(current_time_millis());
+            job.error_message = Some(error);
+        }
+    }
+}
+
+impl Default for UploadQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Gets current time as Unix timestamp in milliseconds.
+fn current_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Runs one staged upload through the same decode/hash/commit pipeline
+/// `submit_new_image` runs inline, returning the gallery filename the
+/// upload ended up under and whether it deduplicated against an existing
+/// image. Always removes the staging file, on both success and failure.
+async fn process_upload(
+    filename: &str,
+    staging_path: &str,
+    upload_hash: &str,
+    stores: &ImageStores,
+) -> Result<(String, bool), String> {
+    // A byte-identical re-upload is caught here, before paying for a decode.
+    if let Some(existing) = metadata::find_by_upload_hash(upload_hash) {
+        let _ = tokio::fs::remove_file(staging_path).await;
+        return Ok((existing, true));
+    }
+
+    let hash_path = staging_path.to_string();
+    let hash_result = tokio::task::spawn_blocking(move || {
+        let img = cache_worker::open_oriented(Path::new(&hash_path))?;
+        let content_hash = processor::content_hash(&img);
+        let sample = img.resize(32, 32, image::imageops::FilterType::Triangle).to_rgb8();
+        let blurhash = blurhash::encode(&sample, 4, 3);
+        let perceptual_hash = phash::compute(&img);
+        Ok((content_hash, blurhash, perceptual_hash))
+    })
+    .await
+    .map_err(|e| format!("upload processing task panicked: {}", e))
+    .and_then(|result| result);
+
+    let (content_hash, blurhash, perceptual_hash) = match hash_result {
+        Ok(result) => result,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(staging_path).await;
+            return Err(e);
+        }
+    };
+
+    if let Some(existing) = metadata::find_by_content_hash(&content_hash) {
+        let _ = tokio::fs::remove_file(staging_path).await;
+        return Ok((existing, true));
+    }
+
+    let staged_bytes = match tokio::fs::read(staging_path).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(staging_path).await;
+            return Err(format!("failed to read staged upload: {}", e));
+        }
+    };
+
+    if let Err(e) = stores.originals.put(filename, staged_bytes).await {
+        let _ = tokio::fs::remove_file(staging_path).await;
+        return Err(e);
+    }
+    let _ = tokio::fs::remove_file(staging_path).await;
+
+    if let Err(e) = metadata::save_content_hash(filename, &content_hash) {
+        error!("Failed to save content hash for {}: {}", filename, e);
+    }
+    if let Err(e) = metadata::save_upload_hash(filename, upload_hash) {
+        error!("Failed to save upload hash for {}: {}", filename, e);
+    }
+    if let Err(e) = metadata::save_blurhash(filename, &blurhash) {
+        error!("Failed to save blurhash for {}: {}", filename, e);
+    }
+    if let Err(e) = metadata::save_perceptual_hash(filename, perceptual_hash) {
+        error!("Failed to save perceptual hash for {}: {}", filename, e);
+    }
+
+    Ok((filename.to_string(), false))
+}
+
+/// Spawns the background worker that drains the upload queue one job at a
+/// time, mirroring `flash_queue::spawn_flash_worker`'s loop-and-sleep shape.
+pub fn spawn_upload_worker(queue_state: UploadQueueState, stores: ImageStores) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        info!("Upload queue worker started");
+
+        loop {
+            let next = queue_state.lock().await.dequeue();
+
+            let Some(pending) = next else {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                continue;
+            };
+
+            queue_state.lock().await.mark_processing(pending.job_id);
+            info!("Processing upload job {}: {}", pending.job_id, pending.filename);
+
+            match process_upload(&pending.filename, &pending.staging_path, &pending.upload_hash, &stores).await {
+                Ok((result_filename, deduplicated)) => {
+                    info!(
+                        "Upload job {} completed: {} (deduplicated: {})",
+                        pending.job_id, result_filename, deduplicated
+                    );
+                    queue_state
+                        .lock()
+                        .await
+                        .mark_done(pending.job_id, result_filename, deduplicated);
+                }
+                Err(e) => {
+                    error!("Upload job {} failed: {}", pending.job_id, e);
+                    queue_state.lock().await.mark_failed(pending.job_id, e);
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,124 @@
+//! Server-side validation for uploaded images.
+//!
+//! Every upload handler is expected to call `validate_upload` before it
+//! copies a `TempFile` anywhere on disk. Validation sniffs the file's magic
+//! bytes to confirm it's a genuine, decodable image rather than trusting the
+//! filename or content-type header, and checks it against configurable
+//! size/dimension/area limits so a malicious or oversized upload (e.g. a
+//! decompression bomb) is refused before it ever touches the filesystem.
+
+use image::io::Reader as ImageReader;
+use rocket::fs::TempFile;
+use std::io::Cursor;
+use tokio::io::AsyncReadExt;
+
+/// Size and dimension limits enforced against an upload before it's saved.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadLimits {
+    pub max_file_size: u64,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_area: u64,
+}
+
+/// Image formats accepted by uploads, regardless of what the filename or
+/// submitted content-type claims.
+const ALLOWED_FORMATS: &[image::ImageFormat] = &[
+    image::ImageFormat::Png,
+    image::ImageFormat::Jpeg,
+    image::ImageFormat::WebP,
+    image::ImageFormat::Gif,
+    image::ImageFormat::Bmp,
+];
+
+/// Reads a `TempFile`'s full contents into memory, regardless of whether
+/// Rocket buffered it or wrote it to a backing file.
+pub async fn read_temp_file(file: &TempFile<'_>) -> Result<Vec<u8>, String> {
+    let mut reader = file
+        .open()
+        .await
+        .map_err(|e| format!("failed to read upload: {}", e))?;
+    let mut buf = Vec::with_capacity(file.len() as usize);
+    reader
+        .read_to_end(&mut buf)
+        .await
+        .map_err(|e| format!("failed to read upload: {}", e))?;
+    Ok(buf)
+}
+
+/// Format and dimensions sniffed from an upload's header during validation,
+/// handed back to the caller so it doesn't have to re-sniff the same bytes
+/// (e.g. to warn when an upload is far from the panel's aspect ratio).
+#[derive(Debug, Clone, Copy)]
+pub struct ImageInfo {
+    pub format: image::ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Short lowercase name for a sniffed format, suitable for display or an API
+/// response (`image::ImageFormat` has no `Serialize` impl of its own).
+pub fn format_name(format: image::ImageFormat) -> &'static str {
+    match format {
+        image::ImageFormat::Png => "png",
+        image::ImageFormat::Jpeg => "jpeg",
+        image::ImageFormat::WebP => "webp",
+        image::ImageFormat::Gif => "gif",
+        image::ImageFormat::Bmp => "bmp",
+        _ => "unknown",
+    }
+}
+
+/// Confirms `file` is a genuine, decodable image within `limits` before the
+/// caller copies it anywhere. Rejects oversized files without reading them,
+/// then sniffs magic bytes and decodes just the image header (not the pixel
+/// data) to check dimensions and area, returning the sniffed format and
+/// dimensions on success.
+pub async fn validate_upload(file: &TempFile<'_>, limits: UploadLimits) -> Result<ImageInfo, String> {
+    let size = file.len();
+    if size > limits.max_file_size {
+        return Err(format!(
+            "file too large ({} bytes, limit is {} bytes)",
+            size, limits.max_file_size
+        ));
+    }
+
+    let buf = read_temp_file(file).await?;
+
+    tokio::task::spawn_blocking(move || validate_bytes(&buf, limits))
+        .await
+        .map_err(|e| format!("validation task panicked: {}", e))?
+}
+
+/// The synchronous half of validation: format sniffing and header decoding.
+/// Runs on a blocking thread since `image`'s decoders are not async.
+fn validate_bytes(buf: &[u8], limits: UploadLimits) -> Result<ImageInfo, String> {
+    let format = image::guess_format(buf).map_err(|_| {
+        "not a recognized image format (expected PNG, JPEG, WebP, GIF, or BMP)".to_string()
+    })?;
+
+    if !ALLOWED_FORMATS.contains(&format) {
+        return Err(format!("unsupported image format: {:?}", format));
+    }
+
+    let (width, height) = ImageReader::with_format(Cursor::new(buf), format)
+        .into_dimensions()
+        .map_err(|e| format!("failed to read image header: {}", e))?;
+
+    if width > limits.max_width || height > limits.max_height {
+        return Err(format!(
+            "image dimensions {}x{} exceed limit of {}x{}",
+            width, height, limits.max_width, limits.max_height
+        ));
+    }
+
+    let area = width as u64 * height as u64;
+    if area > limits.max_area {
+        return Err(format!(
+            "image area {} pixels exceeds limit of {} pixels",
+            area, limits.max_area
+        ));
+    }
+
+    Ok(ImageInfo { format, width, height })
+}